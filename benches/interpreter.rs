@@ -0,0 +1,28 @@
+// Measures raw interpreter throughput, run with `cargo bench --features bench`
+use chiprs::{bus::Bus, cpu::Cpu};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+const CYCLES: u64 = 10_000;
+
+fn bench_execute_cycle(c: &mut Criterion) {
+    let rom = include_bytes!("../roms/breakout.ch8");
+
+    let mut group = c.benchmark_group("interpreter");
+    // Throughput is reported in elements/sec, i.e. instructions/sec
+    group.throughput(Throughput::Elements(CYCLES));
+    group.bench_function("breakout", |b| {
+        b.iter_batched(
+            || (Cpu::new(rom), Bus::new()),
+            |(mut cpu, mut bus)| {
+                for _ in 0..CYCLES {
+                    cpu.execute_cycle(&mut bus);
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_execute_cycle);
+criterion_main!(benches);
@@ -0,0 +1,123 @@
+use crate::assembler::config::{AssemblerConfig, RegisterStyle, RomFormat};
+use crate::assembler::{Assembler, ParseErrors};
+use crate::opcodes::ChipMode;
+
+// Builder returned by `Assembler::builder()`, setting options one at a time before either
+// building the assembler or assembling right away
+#[derive(Debug, Clone, Default)]
+pub struct AssemblerBuilder {
+    source: String,
+    output_path: Option<String>,
+    config: AssemblerConfig,
+}
+
+impl AssemblerBuilder {
+    // Source code to assemble
+    pub fn source(mut self, source: String) -> Self {
+        self.source = source;
+        self
+    }
+
+    pub fn start_address(mut self, start_address: usize) -> Self {
+        self.config = self.config.start_address(start_address);
+        self
+    }
+
+    pub fn mode(mut self, mode: ChipMode) -> Self {
+        self.config = self.config.mode(mode);
+        self
+    }
+
+    pub fn output_format(mut self, output_format: RomFormat) -> Self {
+        self.config = self.config.output_format(output_format);
+        self
+    }
+
+    pub fn register_style(mut self, register_style: RegisterStyle) -> Self {
+        self.config = self.config.register_style(register_style);
+        self
+    }
+
+    // Byte used to pad gaps and the ROM up to pad_size
+    pub fn fill_byte(mut self, fill_byte: u8) -> Self {
+        self.config = self.config.fill_byte(fill_byte);
+        self
+    }
+
+    pub fn pad_size(mut self, pad_size: usize) -> Self {
+        self.config = self.config.pad_size(pad_size);
+        self
+    }
+
+    // Print the lint warnings when running
+    pub fn lint(mut self, lint: bool) -> Self {
+        self.config = self.config.lint(lint);
+        self
+    }
+
+    // File written by `run`, required since the source has no path to derive it from
+    pub fn output_path(mut self, output_path: String) -> Self {
+        self.output_path = Some(output_path);
+        self
+    }
+
+    // Any other options, replacing those set so far
+    pub fn config(mut self, config: AssemblerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn build(self) -> Assembler {
+        let mut assembler = Assembler::with_source(String::new(), self.source, self.config);
+        if let Some(output_path) = self.output_path {
+            assembler.set_output_path(output_path);
+        }
+        assembler
+    }
+
+    pub fn assemble(self) -> Result<Vec<u8>, ParseErrors> {
+        self.build().assemble()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start_address_moves_the_labels() {
+        let builder = Assembler::builder().source("CLS\nloop: JP loop\n".to_string());
+        assert_eq!(
+            builder.clone().assemble().unwrap(),
+            vec![0x00, 0xE0, 0x12, 0x02]
+        );
+        assert_eq!(
+            builder.start_address(0x300).assemble().unwrap(),
+            vec![0x00, 0xE0, 0x13, 0x02]
+        );
+    }
+
+    #[test]
+    fn sets_every_option() {
+        let assembler = Assembler::builder()
+            .source(".org 0x204\nCLS\n".to_string())
+            .mode(ChipMode::SuperChip)
+            .output_format(RomFormat::Hex)
+            .register_style(RegisterStyle::Hex)
+            .fill_byte(0xFF)
+            .pad_size(8)
+            .lint(true)
+            .build();
+        let config = &assembler.config;
+        assert_eq!(config.mode, ChipMode::SuperChip);
+        assert_eq!(config.output_format, RomFormat::Hex);
+        assert_eq!(config.register_style, RegisterStyle::Hex);
+        assert!(config.lint);
+
+        let mut assembler = assembler;
+        assert_eq!(
+            assembler.assemble().unwrap(),
+            vec![0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0xE0, 0xFF, 0xFF]
+        );
+    }
+}
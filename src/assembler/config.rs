@@ -0,0 +1,179 @@
+use crate::assembler::START_ROM;
+use crate::opcodes::ChipMode;
+
+// Encoding of the written ROM file
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RomFormat {
+    // Raw `.ch8` bytes
+    #[default]
+    Binary,
+    // Hex text, as read by the disassembler's `--input-format hex`
+    Hex,
+    // Intel HEX records loading the program at its start address, for flashers
+    IntelHex,
+}
+
+// How register names are written in the source
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RegisterStyle {
+    // VA-VF as well as V10-V15
+    #[default]
+    Any,
+    // V0-VF only, as the disassembler writes with --hex-registers
+    Hex,
+    // V0-V15 only, as the disassembler writes by default
+    Dec,
+}
+
+// Options of the assembler, built up from the defaults:
+// AssemblerConfig::default().start_address(0x300).fill_byte(0xFF)
+#[derive(Debug, Clone)]
+pub struct AssemblerConfig {
+    pub start_address: usize,
+    // Target interpreter, which decides how synthetic instructions such as HALT expand
+    pub mode: ChipMode,
+    pub fill_byte: u8,
+    pub pad_size: Option<usize>,
+    // Warn about likely bugs, such as subroutines without a RET
+    pub lint: bool,
+    pub output_format: RomFormat,
+    pub register_style: RegisterStyle,
+}
+
+impl AssemblerConfig {
+    // Address the first instruction is assembled at
+    pub fn start_address(mut self, start_address: usize) -> Self {
+        self.start_address = start_address;
+        self
+    }
+
+    pub fn mode(mut self, mode: ChipMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    // Byte used to pad any gap between two instructions, and the ROM up to pad_size
+    pub fn fill_byte(mut self, fill_byte: u8) -> Self {
+        self.fill_byte = fill_byte;
+        self
+    }
+
+    // Pad the assembled ROM with the fill byte up to this many bytes
+    pub fn pad_size(mut self, pad_size: usize) -> Self {
+        self.pad_size = Some(pad_size);
+        self
+    }
+
+    pub fn lint(mut self, lint: bool) -> Self {
+        self.lint = lint;
+        self
+    }
+
+    pub fn output_format(mut self, output_format: RomFormat) -> Self {
+        self.output_format = output_format;
+        self
+    }
+
+    pub fn register_style(mut self, register_style: RegisterStyle) -> Self {
+        self.register_style = register_style;
+        self
+    }
+}
+
+impl Default for AssemblerConfig {
+    fn default() -> Self {
+        Self {
+            start_address: START_ROM,
+            mode: ChipMode::Chip8,
+            fill_byte: 0x00,
+            pad_size: None,
+            lint: false,
+            output_format: RomFormat::Binary,
+            register_style: RegisterStyle::Any,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    #[test]
+    fn defaults_to_a_classic_rom_at_0x200() {
+        let config = AssemblerConfig::default();
+        assert_eq!(config.start_address, 0x200);
+        assert_eq!(config.mode, ChipMode::Chip8);
+        assert_eq!(config.fill_byte, 0x00);
+        assert_eq!(config.pad_size, None);
+        assert!(!config.lint);
+        assert_eq!(config.output_format, RomFormat::Binary);
+        assert_eq!(config.register_style, RegisterStyle::Any);
+    }
+
+    #[test]
+    fn applies_a_custom_config() {
+        let config = AssemblerConfig::default()
+            .start_address(0x300)
+            .fill_byte(0xFF)
+            .pad_size(6);
+        let mut assembler = Assembler::from_source(String::from("CLS\nRET\n"));
+        assembler.config = config;
+        let (rom, line_map) = assembler.assemble_with_line_map().unwrap();
+        assert_eq!(rom, vec![0x00, 0xE0, 0x00, 0xEE, 0xFF, 0xFF]);
+        assert_eq!(line_map, vec![(1, 0x300), (2, 0x302)]);
+    }
+
+    #[test]
+    fn parses_registers_in_each_style() {
+        let parse = |style, word: &str| {
+            let mut assembler = Assembler::from_source(String::new());
+            assembler.config = AssemblerConfig::default().register_style(style);
+            assembler.parse_register(word)
+        };
+        let words = ["V0", "VA", "V10", "V15", "VF", "V16", "VG", "V+1", "V"];
+        let parsed = |style| -> Vec<_> { words.iter().map(|word| parse(style, word)).collect() };
+        assert_eq!(
+            parsed(RegisterStyle::Any),
+            vec![
+                Some(0),
+                Some(10),
+                Some(10),
+                Some(15),
+                Some(15),
+                None,
+                None,
+                None,
+                None
+            ]
+        );
+        assert_eq!(
+            parsed(RegisterStyle::Hex),
+            vec![
+                Some(0),
+                Some(10),
+                None,
+                None,
+                Some(15),
+                None,
+                None,
+                None,
+                None
+            ]
+        );
+        assert_eq!(
+            parsed(RegisterStyle::Dec),
+            vec![
+                Some(0),
+                None,
+                Some(10),
+                Some(15),
+                None,
+                None,
+                None,
+                None,
+                None
+            ]
+        );
+    }
+}
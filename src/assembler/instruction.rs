@@ -1,14 +1,60 @@
-// Instructions will consist of:
-//  opcode
-//  Option<LabelAssign>
-//  Option<Register1> 
-//  Option<Register2> 
-//  Option<Immediate>
-//  Option<Label>
-pub struct Instruction;
-
-pub enum Opcode {
-    Cls
+// An assembled instruction or data directive, with where it came from and where it goes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub opcode: String,
+    pub bytes: Vec<u8>,
+    pub address: u16,
+    // Source line the instruction was assembled from
+    pub line: usize,
+    // Emitted by a data directive (DB, DW, DA) rather than an instruction
+    pub is_data: bool,
 }
 
+impl Instruction {
+    pub fn new(opcode: String, bytes: Vec<u8>, address: u16, line: usize) -> Self {
+        Self {
+            opcode,
+            bytes,
+            address,
+            line,
+            is_data: false,
+        }
+    }
 
+    pub fn data(bytes: Vec<u8>, address: u16, line: usize) -> Self {
+        Self {
+            opcode: hex::encode(&bytes),
+            bytes,
+            address,
+            line,
+            is_data: true,
+        }
+    }
+
+    // Big-endian value of the first two bytes, e.g. 0x00E0 for CLS
+    pub fn encoded_u16(&self) -> u16 {
+        self.bytes
+            .iter()
+            .take(2)
+            .fold(0, |word, &byte| word << 8 | byte as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_the_first_word() {
+        let cls = Instruction::new(String::from("00e0"), vec![0x00, 0xE0], 0x200, 1);
+        assert_eq!(cls.encoded_u16(), 0x00E0);
+        assert_eq!(
+            Instruction::data(vec![0x12, 0x34, 0x56], 0x202, 2).encoded_u16(),
+            0x1234
+        );
+        assert_eq!(
+            Instruction::data(vec![0xAB], 0x205, 3).encoded_u16(),
+            0x00AB
+        );
+    }
+}
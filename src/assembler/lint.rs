@@ -0,0 +1,77 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::assembler::instruction::Instruction;
+use crate::disassembler::Decoded;
+
+// Subroutines that can't reach a RET, as (line of the CALL, subroutine address)
+pub(crate) fn unreturned_calls(instructions: &[Instruction]) -> Vec<(usize, u16)> {
+    let code: HashMap<u16, u16> = instructions
+        .iter()
+        .filter(|inst| !inst.is_data && inst.bytes.len() == 2)
+        .map(|inst| (inst.address, inst.encoded_u16()))
+        .collect();
+
+    instructions
+        .iter()
+        .filter(|inst| {
+            !inst.is_data && inst.bytes.len() == 2 && inst.encoded_u16() & 0xF000 == 0x2000
+        })
+        .map(|inst| (inst.line, inst.encoded_u16() & 0x0FFF))
+        .filter(|(_, target)| !reaches_ret(&code, *target))
+        .collect()
+}
+
+// Follow control flow from a subroutine entry, treating nested calls as returning
+fn reaches_ret(code: &HashMap<u16, u16>, entry: u16) -> bool {
+    let mut visited = HashSet::new();
+    let mut pending = vec![entry];
+
+    while let Some(address) = pending.pop() {
+        let opcode = match code.get(&address) {
+            Some(opcode) if visited.insert(address) => *opcode,
+            _ => continue,
+        };
+        if opcode == 0x00EE {
+            return true;
+        }
+        let decoded = Decoded::new(address, opcode);
+        let successors = if opcode & 0xF000 == 0x2000 {
+            vec![address as usize + 2]
+        } else {
+            decoded.successors()
+        };
+        pending.extend(successors.into_iter().map(|next| next as u16));
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::Assembler;
+
+    fn lint(source: &str) -> Vec<String> {
+        let mut assembler = Assembler::from_source(source.to_string());
+        assembler.assemble().unwrap();
+        assembler.lint()
+    }
+
+    #[test]
+    fn warns_about_subroutines_without_ret() {
+        let warnings = lint("CALL sub\nend: JP end\nsub: ADD V0, 1\nloop: JP loop\n");
+        assert_eq!(
+            warnings,
+            vec!["[Line 1] Subroutine at 0x204 never reaches a RET"]
+        );
+        assert!(lint("CALL sub\nend: JP end\nsub: SE V0, 1\nRET\nJP sub\n").is_empty());
+    }
+
+    #[test]
+    fn ignores_data() {
+        // A DW that looks like a CALL, and a RET that is only data
+        assert!(lint("CALL sub\nend: JP end\nDW 0x2300\nsub: RET\n").is_empty());
+        assert_eq!(
+            lint("CALL sub\nend: JP end\nsub: CLS\nDB 0x00, 0xEE\n").len(),
+            1
+        );
+    }
+}
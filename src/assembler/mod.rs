@@ -1,32 +1,2320 @@
-mod lexer;
-mod parser;
-mod token;
+mod builder;
+mod config;
 mod instruction;
+mod lint;
+mod session;
 
-use std::fs;
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
 
-use crate::assembler::lexer::Lexer;
+use crate::opcodes::ChipMode;
+use crate::ram::RAM_SIZE;
+
+pub use crate::assembler::builder::AssemblerBuilder;
+pub use crate::assembler::config::{AssemblerConfig, RegisterStyle, RomFormat};
+pub use crate::assembler::instruction::Instruction;
+pub use crate::assembler::session::AssemblerSession;
+
+const START_ROM: usize = 512; // 0x200
+
+// Special operands of LD/ADD/DRW, which can't be used as label or constant names
+const RESERVED_WORDS: [&str; 8] = ["I", "DT", "ST", "F", "B", "K", "R", "HF"];
+
+const MNEMONICS: [&str; 33] = [
+    "CLS", "RET", "JP", "CALL", "SE", "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN",
+    "SHL", "RND", "DRW", "SKP", "SKNP", "DB", "DW", "DA", "EXIT", "HALT", "SCD", "SCR", "SCL",
+    "LOW", "HIGH", "SCU", "PLANE", "AUDIO", "PITCH",
+];
+
+// Levenshtein distance between two words, used to suggest fixes for typos
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + if ca == *cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+// Error in a source line, collected by the assembler and available through `errors()`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub msg: String,
+    pub line: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[Line {}] {}", self.line, self.msg)
+    }
+}
+
+impl ParseError {
+    // Whether the only problem is a name that isn't defined (yet)
+    pub(crate) fn is_undefined_name(&self) -> bool {
+        self.msg.starts_with("Undefined label") || self.msg.starts_with("Undefined name")
+    }
+}
+
+// Every error of a failed assembly, in source order; displayed as a summary line followed by
+// the errors themselves
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseErrors(pub Vec<ParseError>);
+
+impl ParseErrors {
+    // e.g. `assembly failed: 3 errors (first at line 12)`
+    pub fn summary(&self) -> String {
+        let count = match self.0.len() {
+            1 => "1 error".to_string(),
+            n => format!("{} errors", n),
+        };
+        match self.0.first() {
+            Some(first) => format!("assembly failed: {} (first at line {})", count, first.line),
+            None => format!("assembly failed: {}", count),
+        }
+    }
+}
+
+impl fmt::Display for ParseErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.summary())?;
+        for error in &self.0 {
+            write!(f, "\n{}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseErrors {}
+
+impl std::ops::Deref for ParseErrors {
+    type Target = [ParseError];
+
+    fn deref(&self) -> &[ParseError] {
+        &self.0
+    }
+}
+
+impl IntoIterator for ParseErrors {
+    type Item = ParseError;
+    type IntoIter = std::vec::IntoIter<ParseError>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+// (source line, address) of every assembled instruction
+pub type LineMap = Vec<(usize, u16)>;
 
 pub struct Assembler {
     source_path: String,
     source_code: String,
+    output_path: Option<String>,
+    instructions: Vec<Instruction>,
+    // Addresses of the labels defined so far
+    labels: HashMap<String, u16>,
+    // Values of the `name EQU value` constants defined so far
+    constants: HashMap<String, u16>,
+    line: usize,
+    address: usize,
+    config: AssemblerConfig,
+    // Errors of the last assembly, in source order
+    errors: Vec<ParseError>,
+}
+
+// Assemble source code held in memory, without reading or writing any file
+pub fn assemble(source_code: &str) -> Result<Vec<u8>, ParseErrors> {
+    Assembler::from_source(source_code.to_string()).assemble()
+}
+
+// Assemble source code held in memory, returning the ROM as a lowercase hex string
+pub fn assemble_hex(source_code: &str) -> Result<String, ParseErrors> {
+    assemble(source_code).map(hex::encode)
+}
+
+// Intel HEX records of a ROM loaded at `start_address`, 16 data bytes per record followed by the
+// end of file record, e.g. `:0202000000E01C` for a CLS at 0x200
+pub fn to_intel_hex(bytes: &[u8], start_address: u16) -> String {
+    let mut records = String::new();
+    for (idx, chunk) in bytes.chunks(16).enumerate() {
+        let address = start_address.wrapping_add(idx as u16 * 16);
+        let mut record = vec![chunk.len() as u8, (address >> 8) as u8, address as u8, 0x00];
+        record.extend_from_slice(chunk);
+        // Two's complement of the sum of every other byte of the record
+        let checksum = record
+            .iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+            .wrapping_neg();
+        record.push(checksum);
+        records.push_str(&format!(":{}\n", hex::encode_upper(record)));
+    }
+    records.push_str(":00000001FF\n");
+    records
 }
 
 impl Assembler {
-    pub fn new(source_path: String) -> Self {
-        let source_code = fs::read_to_string(&source_path).expect("Unable to read file.");
+    pub fn new(source_path: String) -> io::Result<Self> {
+        Self::with_config(source_path, AssemblerConfig::default())
+    }
+
+    // Chained setup, e.g. for source held in memory:
+    // Assembler::builder().source(text).start_address(0x300).assemble()
+    pub fn builder() -> AssemblerBuilder {
+        AssemblerBuilder::default()
+    }
+
+    pub fn with_config(source_path: String, config: AssemblerConfig) -> io::Result<Self> {
+        let source_code = fs::read_to_string(&source_path)?;
+        Ok(Self::with_source(source_path, source_code, config))
+    }
+
+    // Assemble source code held in memory, the output file then has to be set explicitly
+    pub fn from_source(source_code: String) -> Self {
+        Self::with_source(String::new(), source_code, AssemblerConfig::default())
+    }
 
+    fn with_source(source_path: String, source_code: String, config: AssemblerConfig) -> Self {
         Self {
             source_code,
             source_path,
+            output_path: None,
+            instructions: Vec::new(),
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            line: 1,
+            address: config.start_address,
+            config,
+            errors: Vec::new(),
         }
     }
 
-    pub fn run(&self) {
+    pub fn config(&self) -> &AssemblerConfig {
+        &self.config
+    }
+
+    // Instructions of the last assembly in address order, e.g. for their `encoded_u16` words
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    // Errors found by the last `run`/`assemble`, empty when the source assembled cleanly
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    // Write the ROM to an explicit path instead of deriving it from the source name
+    pub fn set_output_path(&mut self, output_path: String) {
+        self.output_path = Some(output_path);
+    }
+
+    // Byte used to pad any gap between two instructions in the output
+    pub fn set_fill_byte(&mut self, fill_byte: u8) {
+        self.config.fill_byte = fill_byte;
+    }
+
+    pub fn run(&mut self) {
         println!("Running assembler");
-        let mut lexer = Lexer::new(&self.source_code);
-        lexer.scan_tokens();
-        let tokens = lexer.tokens();
-        dbg!(tokens);
+        if self.source_code.trim().is_empty() {
+            println!(
+                "Warning: {} is empty, no instructions assembled",
+                self.source_path
+            );
+            return;
+        }
+
+        // A ROM missing the erroneous lines would be subtly wrong, so nothing gets written
+        let bytes = match self.assemble() {
+            Ok(bytes) => bytes,
+            Err(errors) => {
+                println!("{}", errors);
+                println!("No file written");
+                return;
+            }
+        };
+        if bytes.is_empty() {
+            println!("Warning: no instructions assembled, no file written");
+            return;
+        }
+        if self.config.lint {
+            for warning in self.lint() {
+                println!("Warning: {}", warning);
+            }
+        }
+        match self.write_file() {
+            Ok(path) => println!("File assembled: {}", path),
+            Err(e) => println!("Error: {}", e),
+        }
+    }
+
+    // Assemble the source code, returning the ROM bytes or every error found, without touching
+    // the filesystem
+    pub fn assemble(&mut self) -> Result<Vec<u8>, ParseErrors> {
+        self.parse_lines();
+        if self.errors.is_empty() {
+            Ok(self.rom_bytes())
+        } else {
+            Err(ParseErrors(self.errors.clone()))
+        }
+    }
+
+    // Warnings about likely bugs in the last assembled program
+    pub fn lint(&self) -> Vec<String> {
+        lint::unreturned_calls(&self.instructions)
+            .into_iter()
+            .map(|(line, target)| {
+                format!(
+                    "[Line {}] Subroutine at {:#05x} never reaches a RET",
+                    line, target
+                )
+            })
+            .collect()
+    }
+
+    // Labels of the last assembled program as `name = 0x2A0` lines, sorted by address, in the
+    // symbol file format read by the disassembler's `--symbols`
+    pub fn symbol_map(&self) -> String {
+        let mut labels: Vec<(&String, &u16)> = self.labels.iter().collect();
+        labels.sort_by_key(|&(name, address)| (*address, name));
+        labels
+            .into_iter()
+            .map(|(name, address)| format!("{} = 0x{:03X}\n", name, address))
+            .collect()
+    }
+
+    pub fn write_symbol_map(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.symbol_map())
+    }
+
+    // Assemble the source code, returning the ROM as a lowercase hex string
+    pub fn assemble_hex(&mut self) -> Result<String, ParseErrors> {
+        self.assemble().map(hex::encode)
+    }
+
+    // Assemble the source code, also returning the (source line, address) of every instruction
+    pub fn assemble_with_line_map(&mut self) -> Result<(Vec<u8>, LineMap), ParseErrors> {
+        let bytes = self.assemble()?;
+        let line_map = self
+            .instructions
+            .iter()
+            .map(|inst| (inst.line, inst.address))
+            .collect();
+        Ok((bytes, line_map))
+    }
+
+    // Two passes over the source: the first only records the address of every label, so the
+    // second can resolve references to labels defined further down
+    fn parse_lines(&mut self) {
+        self.labels.clear();
+        self.constants.clear();
+        self.errors.clear();
+        self.parse_pass(true);
+        self.parse_pass(false);
+        // Label errors come from the first pass, so restore the source order
+        self.errors.sort_by_key(|error| error.line);
+    }
+
+    fn parse_pass(&mut self, first_pass: bool) {
+        self.instructions.clear();
+        self.address = self.config.start_address;
+
+        let source_code = self.source_code.clone();
+        for (idx, line) in source_code.lines().enumerate() {
+            self.line = idx + 1;
+            let mut code = self.strip_comment(line).trim();
+            // A leading `name:` labels the address of the instruction that follows it,
+            // on the same line or on the next ones
+            if let Some(name) = code
+                .split_whitespace()
+                .next()
+                .and_then(|word| word.strip_suffix(':'))
+            {
+                if first_pass {
+                    if let Err(e) = self.define_label(name) {
+                        self.errors.push(e);
+                    }
+                }
+                code = code[name.len() + 1..].trim();
+            }
+            // Nothing to assemble on blank lines or lines made only of separators, so they
+            // neither emit an instruction nor move the address
+            let words = self.split_words(code);
+            if words.is_empty() {
+                continue;
+            }
+            if words.len() > 2 && words[1].eq_ignore_ascii_case("EQU") {
+                if let Err(e) = self.define_constant(&words[0], &words[2..], first_pass) {
+                    self.errors.push(e);
+                }
+                continue;
+            }
+            if words[0].eq_ignore_ascii_case(".org") {
+                match self.parse_org(&words[1..]) {
+                    Ok(address) => self.address = address,
+                    // Reported once, by the second pass
+                    Err(_) if first_pass => {}
+                    Err(e) => self.errors.push(e),
+                }
+                continue;
+            }
+            match self
+                .parse_instruction(code)
+                .and_then(|instruction| self.check_fits(instruction))
+            {
+                Ok(instruction) => {
+                    self.address += instruction.bytes.len();
+                    self.instructions.push(instruction);
+                }
+                // Most likely a forward reference, resolved by the second pass; only the value is
+                // unknown, so the line still takes its full size and later labels stay in place
+                Err(e) => {
+                    if !first_pass {
+                        self.errors.push(e);
+                    }
+                    self.address += self.encoded_size(code);
+                }
+            }
+        }
+    }
+
+    // Concatenate the instruction bytes, padding any gap between instructions with the fill byte
+    fn rom_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut next_address = self.config.start_address;
+        for inst in self.instructions.iter() {
+            let address = inst.address as usize;
+            if address > next_address {
+                bytes.resize(bytes.len() + address - next_address, self.config.fill_byte);
+            }
+            bytes.extend_from_slice(&inst.bytes);
+            next_address = address + inst.bytes.len();
+        }
+        if let Some(pad_size) = self.config.pad_size {
+            if bytes.len() < pad_size {
+                bytes.resize(pad_size, self.config.fill_byte);
+            }
+        }
+        bytes
+    }
+
+    fn parse_instruction(&self, line: &str) -> Result<Instruction, ParseError> {
+        let tokens: Vec<String> = self
+            .split_words(line)
+            .into_iter()
+            .enumerate()
+            .map(|(idx, word)| self.normalize_case(word, idx == 0))
+            .collect();
+        let words: Vec<&str> = tokens.iter().map(String::as_str).collect();
+
+        let mnemonic = match words.first() {
+            Some(mnemonic) => *mnemonic,
+            None => {
+                return Err(ParseError {
+                    line: self.line,
+                    msg: format!("Unable to parse line {}", line),
+                })
+            }
+        };
+        let min_operands = match mnemonic {
+            "JP" | "CALL" | "SHR" | "SHL" | "SKP" | "SKNP" | "SCD" | "SCU" | "PLANE" | "PITCH" => 1,
+            "SE" | "SNE" | "LD" | "ADD" | "OR" | "AND" | "XOR" | "SUB" | "SUBN" | "RND" => 2,
+            "DRW" => 3,
+            _ => 0,
+        };
+        if words.len() - 1 < min_operands {
+            return Err(ParseError {
+                line: self.line,
+                msg: format!("{} expects at least {} operand(s)", mnemonic, min_operands),
+            });
+        }
+
+        let opcode = match mnemonic {
+            "DB" => return self.parse_db(&words[1..]),
+            "DW" => return self.parse_dw(&words[1..]),
+            // Taken from the raw line, since splitting would break up the quoted text
+            "DA" => return self.parse_string(&line[mnemonic.len()..]),
+            "JP" => self.parse_jp(&words[1..])?,
+            "CALL" => self.parse_call(&words[1..])?,
+            "RET" => String::from("00EE"),
+            "EXIT" => self.parse_exit()?,
+            "HALT" => self.parse_halt(),
+            "SCD" => self.parse_scd(words[1])?,
+            "SCR" => self.parse_schip(mnemonic, "00FB")?,
+            "SCL" => self.parse_schip(mnemonic, "00FC")?,
+            "LOW" => self.parse_schip(mnemonic, "00FE")?,
+            "HIGH" => self.parse_schip(mnemonic, "00FF")?,
+            "SCU" => self.parse_scu(words[1])?,
+            "PLANE" => self.parse_plane(words[1])?,
+            "AUDIO" => self.parse_audio()?,
+            "PITCH" => self.parse_pitch(words[1])?,
+            "CLS" => String::from("00E0"),
+            "SE" => self.parse_se(&words[1..])?,
+            "SNE" => self.parse_sne(&words[1..])?,
+            "LD" => self.parse_ld(&words[1..])?,
+            "ADD" => self.parse_add(&words[1..])?,
+            "OR" => self.parse_or(&words[1..])?,
+            "AND" => self.parse_and(&words[1..])?,
+            "XOR" => self.parse_xor(&words[1..])?,
+            "SUB" => self.parse_sub(&words[1..])?,
+            "SHR" => self.parse_shr(&words[1..])?,
+            "SUBN" => self.parse_subn(&words[1..])?,
+            "SHL" => self.parse_shl(&words[1..])?,
+            "RND" => self.parse_rnd(&words[1..])?,
+            "DRW" => self.parse_drw(&words[1..])?,
+            "SKP" => self.parse_skp(&words[1..])?,
+            "SKNP" => self.parse_sknp(words[1])?,
+            _ => return Err(self.unknown_mnemonic(mnemonic)),
+        };
+        self.build_instruction(opcode, self.address)
+    }
+
+    // `.org addr`: assemble the following instructions from addr on, the gap being filled with
+    // the fill byte. It can't move backwards over bytes that are already assembled.
+    fn parse_org(&self, words: &[String]) -> Result<usize, ParseError> {
+        let address = self.parse_target(&words.join(" "))? as usize;
+        if address < self.address {
+            return Err(ParseError {
+                line: self.line,
+                msg: format!(
+                    ".org {:#x} is below the current address {:#x}",
+                    address, self.address
+                ),
+            });
+        }
+        Ok(address)
+    }
+
+    // Mnemonics, registers and special operands are case-insensitive, so `ld v0, dt` is read as
+    // `LD V0, DT`; labels and constants keep their case
+    fn normalize_case(&self, word: String, is_mnemonic: bool) -> String {
+        let upper = word.to_ascii_uppercase();
+        if is_mnemonic
+            || self.parse_register(&upper).is_some()
+            || self.reserved_word(&upper).is_some()
+        {
+            upper
+        } else {
+            word
+        }
+    }
+
+    // Error for an unrecognized mnemonic, suggesting the closest known one if it looks like a typo
+    fn unknown_mnemonic(&self, mnemonic: &str) -> ParseError {
+        let suggestion = MNEMONICS
+            .iter()
+            .map(|known| (edit_distance(mnemonic, known), known))
+            .filter(|(distance, known)| *distance <= known.len() / 2)
+            .min();
+        let msg = match suggestion {
+            Some((_, known)) => {
+                format!("Unknown mnemonic '{}'; did you mean '{}'?", mnemonic, known)
+            }
+            None => format!("Unknown mnemonic '{}'", mnemonic),
+        };
+        ParseError {
+            line: self.line,
+            msg,
+        }
+    }
+
+    // Remove a `;` or `//` comment running to the end of the line, ignoring comment markers
+    // inside double-quoted text and character literals so operands keep them
+    fn strip_comment<'a>(&self, line: &'a str) -> &'a str {
+        let mut in_quotes = false;
+        let mut in_char = false;
+        let mut chars = line.char_indices().peekable();
+        while let Some((idx, c)) = chars.next() {
+            match c {
+                '"' if !in_char => in_quotes = !in_quotes,
+                '\'' if !in_quotes => in_char = !in_char,
+                _ if in_char => {}
+                // Skip an escaped character, so `\"` doesn't end the quoted text
+                '\\' if in_quotes => {
+                    chars.next();
+                }
+                ';' if !in_quotes => return &line[..idx],
+                '/' if !in_quotes && matches!(chars.peek(), Some((_, '/'))) => return &line[..idx],
+                _ => {}
+            }
+        }
+        line
+    }
+
+    // Split a line on whitespace and commas, keeping a bracketed operand such as `[I]`
+    // together as a single word (with any inner whitespace removed), and a character
+    // literal such as `' '` intact
+    fn split_words(&self, line: &str) -> Vec<String> {
+        let mut words = Vec::new();
+        let mut word = String::new();
+        let mut in_brackets = false;
+        let mut in_char = false;
+
+        for c in line.chars() {
+            match c {
+                '\'' => {
+                    in_char = !in_char;
+                    word.push(c);
+                }
+                _ if in_char => word.push(c),
+                '[' => {
+                    in_brackets = true;
+                    word.push(c);
+                }
+                ']' => {
+                    in_brackets = false;
+                    word.push(c);
+                }
+                ' ' | '\t' if in_brackets => {}
+                ' ' | '\t' | ',' => {
+                    if !word.is_empty() {
+                        words.push(std::mem::take(&mut word));
+                    }
+                }
+                _ => word.push(c),
+            }
+        }
+        if !word.is_empty() {
+            words.push(word);
+        }
+        words
+    }
+
+    fn build_instruction(&self, opcode: String, address: usize) -> Result<Instruction, ParseError> {
+        let mut bytes = [0u8; 2];
+        match hex::decode_to_slice(&opcode, &mut bytes as &mut [u8]) {
+            Ok(_) => Ok(Instruction::new(
+                opcode,
+                bytes.to_vec(),
+                address as u16,
+                self.line,
+            )),
+            Err(e) => Err(ParseError {
+                line: self.line,
+                msg: format!("Failed to encode instruction {}: {}", opcode, e),
+            }),
+        }
+    }
+
+    // Reject an instruction running past the end of memory, before its address is truncated
+    fn check_fits(&self, instruction: Instruction) -> Result<Instruction, ParseError> {
+        let size = instruction.bytes.len();
+        if self.address + size > RAM_SIZE {
+            let msg = if self.address < RAM_SIZE {
+                format!(
+                    "{}-byte instruction at {:#x} runs past the end of memory at {:#x}",
+                    size,
+                    self.address,
+                    RAM_SIZE - 1
+                )
+            } else {
+                format!(
+                    "Address {:#x} is out of range, memory ends at {:#x}",
+                    self.address,
+                    RAM_SIZE - 1
+                )
+            };
+            return Err(ParseError {
+                line: self.line,
+                msg,
+            });
+        }
+        Ok(instruction)
+    }
+
+    fn define_label(&mut self, name: &str) -> Result<(), ParseError> {
+        self.check_symbol_name(name)?;
+        // A label may sit right after the last byte of memory, but no further
+        if self.address > RAM_SIZE {
+            return Err(ParseError {
+                line: self.line,
+                msg: format!(
+                    "Label `{}` at {:#x} is out of range, memory ends at {:#x}",
+                    name,
+                    self.address,
+                    RAM_SIZE - 1
+                ),
+            });
+        }
+        if self.labels.contains_key(name) || self.constants.contains_key(name) {
+            return Err(ParseError {
+                line: self.line,
+                msg: format!("Label `{}` is already defined", name),
+            });
+        }
+        self.labels.insert(name.to_string(), self.address as u16);
+        Ok(())
+    }
+
+    // `name EQU value`, where value is evaluated like an address operand (numbers, `$`, labels
+    // and earlier constants with `+`/`-` offsets). A constant referring to a label further down
+    // is only known from the second pass on, which is also when undefined names are reported.
+    fn define_constant(
+        &mut self,
+        name: &str,
+        value: &[String],
+        first_pass: bool,
+    ) -> Result<(), ParseError> {
+        if first_pass {
+            self.check_symbol_name(name)?;
+            if self.labels.contains_key(name) || self.constants.contains_key(name) {
+                return Err(ParseError {
+                    line: self.line,
+                    msg: format!("Constant `{}` is already defined", name),
+                });
+            }
+        } else if self.constants.contains_key(name) || self.check_symbol_name(name).is_err() {
+            // Already defined, or a bad name that the first pass reported
+            return Ok(());
+        }
+
+        let expression = value.join(" ");
+        let msg = match self.eval_address(&expression) {
+            Some(value) if (0..=0xFFFF).contains(&value) => {
+                self.constants.insert(name.to_string(), value as u16);
+                return Ok(());
+            }
+            _ if first_pass => return Ok(()),
+            Some(value) => format!(
+                "Out of range constant {} ({:#x}), max is 0xffff",
+                name, value
+            ),
+            None => match self.undefined_label(&expression) {
+                Some(undefined) => format!("Undefined name `{}` in constant {}", undefined, name),
+                None => format!("Unable to parse constant {} {}", name, expression),
+            },
+        };
+        Err(ParseError {
+            line: self.line,
+            msg,
+        })
+    }
+
+    // Validate a label/constant name: it must not shadow a register or a special operand
+    fn check_symbol_name(&self, name: &str) -> Result<(), ParseError> {
+        let upper = name.to_uppercase();
+        let msg = if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            format!("`{}` is not a valid symbol name", name)
+        } else if RESERVED_WORDS.contains(&upper.as_str()) {
+            format!("`{}` is a reserved operand name", name)
+        } else if self.parse_register(&upper).is_some() {
+            format!("`{}` is a register name", name)
+        } else {
+            return Ok(());
+        };
+        Err(ParseError {
+            line: self.line,
+            msg,
+        })
+    }
+
+    // Reserved operand named by a word, with `[I]` standing for I
+    fn reserved_word(&self, word: &str) -> Option<&'static str> {
+        let word = if word == "[I]" { "I" } else { word };
+        RESERVED_WORDS
+            .iter()
+            .copied()
+            .find(|reserved| *reserved == word)
+    }
+
+    // Evaluate an address operand: a number, `$`/`.` for the current address or a label,
+    // followed by any number of `+`/`-` offsets, e.g. `loop - 2`
+    fn eval_address(&self, operand: &str) -> Option<i64> {
+        let mut total: i64 = 0;
+        let mut sign = 1;
+        let mut rest = operand;
+        loop {
+            let end = rest.find(&['+', '-'][..]).unwrap_or(rest.len());
+            total += sign * self.eval_address_term(rest[..end].trim())? as i64;
+            if end == rest.len() {
+                break;
+            }
+            sign = if rest[end..].starts_with('-') { -1 } else { 1 };
+            rest = &rest[end + 1..];
+        }
+        Some(total)
+    }
+
+    fn eval_address_term(&self, term: &str) -> Option<u32> {
+        match term {
+            "$" | "." => Some(self.address as u32),
+            _ => self
+                .parse_digit(term)
+                .or_else(|| self.labels.get(term).map(|&address| address as u32)),
+        }
+    }
+
+    // First label of an address operand that isn't defined
+    fn undefined_label<'a>(&self, operand: &'a str) -> Option<&'a str> {
+        operand.split(&['+', '-'][..]).map(str::trim).find(|term| {
+            term.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && term.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                && !self.labels.contains_key(*term)
+                && !self.constants.contains_key(*term)
+        })
+    }
+
+    // Evaluate a numeric operand, where a `<` or `>` prefix selects the low or high byte of the value
+    fn eval_operand(&self, word: &str) -> Option<u32> {
+        let digits = word.trim_start_matches(&['<', '>'][..]);
+        let selectors = &word[..word.len() - digits.len()];
+        let mut value = if selectors.is_empty() {
+            self.parse_digit(digits)?
+        } else {
+            // The byte of an address, e.g. `>loop` for the high byte of a label
+            let address = self.eval_address(digits)?;
+            if !(0..=0xFFFF).contains(&address) {
+                return None;
+            }
+            address as u32
+        };
+        // Selectors apply from the innermost (rightmost) outwards
+        for selector in selectors.chars().rev() {
+            value = if selector == '<' {
+                value & 0xFF
+            } else {
+                value >> 8
+            };
+        }
+        Some(value)
+    }
+
+    // Numeric literal, character literal or constant. Wider than any operand, so values that
+    // don't fit are reported as out of range rather than unparsable.
+    fn parse_digit(&self, word: &str) -> Option<u32> {
+        self.parse_number(word)
+            .or_else(|| self.parse_char(word))
+            .or_else(|| self.constants.get(word).map(|&value| value as u32))
+    }
+
+    // Character literal such as 'A', standing for its ASCII code
+    fn parse_char(&self, word: &str) -> Option<u32> {
+        let inner = word.strip_prefix('\'')?.strip_suffix('\'')?;
+        let mut chars = inner.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii() => Some(c as u32),
+            _ => None,
+        }
+    }
+
+    fn parse_number(&self, word: &str) -> Option<u32> {
+        let (digits, radix) =
+            if let Some(binary) = word.strip_prefix("0b").or_else(|| word.strip_prefix("0B")) {
+                (binary, 2)
+            } else if let Some(hex) = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+                (hex, 16)
+            } else {
+                (word, 10)
+            };
+        // The integer parser would also take a sign, as in `+5` or `0x+5`
+        if digits.starts_with(&['+', '-'][..]) {
+            return None;
+        }
+        u32::from_str_radix(digits, radix).ok()
+    }
+
+    // Address operand, which has to fit in 12 bits
+    fn parse_addr(&self, operand: &str) -> Result<u16, ParseError> {
+        self.parse_addr_from(operand, 0)
+    }
+
+    // Jump or call target. One relative to a label or `$` can't end up below the start of the
+    // program, which catches offsets such as `loop - 600`; plain numbers may go anywhere.
+    fn parse_target(&self, operand: &str) -> Result<u16, ParseError> {
+        let is_relative = operand
+            .split(&['+', '-'][..])
+            .any(|term| self.parse_digit(term.trim()).is_none());
+        let min = if is_relative {
+            self.config.start_address as i64
+        } else {
+            0
+        };
+        self.parse_addr_from(operand, min)
+    }
+
+    fn parse_addr_from(&self, operand: &str, min: i64) -> Result<u16, ParseError> {
+        let msg = match self.eval_address(operand) {
+            Some(address) if (min..=0xFFF).contains(&address) => return Ok(address as u16),
+            Some(address) if address < min => {
+                format!("Address {} ({}) is below {:#x}", operand, address, min)
+            }
+            Some(address) => format!(
+                "Out of range address {} ({:#x}), max is 0xfff",
+                operand, address
+            ),
+            None => match self.undefined_label(operand) {
+                Some(name) => format!("Undefined label `{}`", name),
+                None => format!("Unable to parse address {}", operand),
+            },
+        };
+        Err(ParseError {
+            line: self.line,
+            msg,
+        })
+    }
+
+    // Immediate byte operand (kk)
+    fn parse_byte(&self, word: &str) -> Result<u16, ParseError> {
+        self.check_range(self.parse_digit(word), word, "byte", 0xFF)
+    }
+
+    // Nibble operand (n), e.g. the sprite height of DRW
+    fn parse_nibble(&self, word: &str) -> Result<u16, ParseError> {
+        self.check_range(self.parse_digit(word), word, "nibble", 0xF)
+    }
+
+    fn check_range(
+        &self,
+        value: Option<u32>,
+        word: &str,
+        kind: &str,
+        max: u16,
+    ) -> Result<u16, ParseError> {
+        let msg = match value {
+            Some(value) if value <= max as u32 => return Ok(value as u16),
+            Some(value) => format!(
+                "Out of range {} {} ({:#x}), max is {:#x}",
+                kind, word, value, max
+            ),
+            None if word.starts_with('\'') => format!(
+                "Invalid character literal {}, expected a single ASCII character",
+                word
+            ),
+            None => match self.undefined_label(word.trim_start_matches(&['<', '>'][..])) {
+                Some(name) => format!("Undefined label `{}`", name),
+                None => format!("Unable to parse {} {}", kind, word),
+            },
+        };
+        Err(ParseError {
+            line: self.line,
+            msg,
+        })
+    }
+
+    // V0-VF, with the decimal V10-V15 also accepted for VA-VF unless the register style
+    // only allows one of them
+    fn parse_register(&self, word: &str) -> Option<u16> {
+        let index = word.strip_prefix('V')?;
+        // Only plain digits, the integer parsers would also take a sign such as V+1
+        let is_hex = index.len() == 1 && index.bytes().all(|b| b.is_ascii_hexdigit());
+        let is_dec = !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit());
+        let register = match self.config.register_style {
+            RegisterStyle::Hex | RegisterStyle::Any if is_hex => {
+                u16::from_str_radix(index, 16).ok()
+            }
+            RegisterStyle::Dec | RegisterStyle::Any if is_dec => index.parse::<u16>().ok(),
+            _ => None,
+        };
+        register.filter(|&register| register <= 0xF)
+    }
+
+    // Bytes a line assembles to, known from its operand count even when their values aren't
+    fn encoded_size(&self, code: &str) -> usize {
+        let words = self.split_words(code);
+        let operands = words.len().saturating_sub(1);
+        match words
+            .first()
+            .map(|word| word.to_ascii_uppercase())
+            .as_deref()
+        {
+            Some("DB") => operands,
+            Some("DW") => 2 * operands,
+            Some("DA") => self
+                .parse_string(&code[2..])
+                .map_or(0, |instruction| instruction.bytes.len()),
+            _ => 2,
+        }
+    }
+
+    // DB byte, byte, ...: raw data bytes, e.g. sprites written as binary literals
+    fn parse_db(&self, words: &[&str]) -> Result<Instruction, ParseError> {
+        if words.is_empty() {
+            return Err(ParseError {
+                line: self.line,
+                msg: String::from("DB requires at least one byte"),
+            });
+        }
+
+        let mut bytes = Vec::new();
+        for word in words {
+            let byte = self.check_range(self.eval_operand(word), word, "DB byte", 0xFF)?;
+            bytes.push(byte as u8);
+        }
+        Ok(Instruction::data(bytes, self.address as u16, self.line))
+    }
+
+    // DW word, word, ...: raw 16-bit words, stored big-endian (high byte first) like opcodes
+    fn parse_dw(&self, words: &[&str]) -> Result<Instruction, ParseError> {
+        if words.is_empty() {
+            return Err(ParseError {
+                line: self.line,
+                msg: String::from("DW requires at least one word"),
+            });
+        }
+
+        let mut bytes = Vec::new();
+        for word in words {
+            // Labels and `$` are accepted too, for tables of addresses
+            let value = self
+                .eval_operand(word)
+                .or_else(|| self.eval_address_term(word));
+            let value = self.check_range(value, word, "DW word", 0xFFFF)?;
+            bytes.extend_from_slice(&value.to_be_bytes());
+        }
+        Ok(Instruction::data(bytes, self.address as u16, self.line))
+    }
+
+    // DA "text": one byte per character (UTF-8), with \n, \0, \\ and \" escapes
+    fn parse_string(&self, operand: &str) -> Result<Instruction, ParseError> {
+        let error = |msg: String| ParseError {
+            line: self.line,
+            msg,
+        };
+        let text = operand.trim().strip_prefix('"').ok_or_else(|| {
+            error(format!(
+                "DA expects a quoted string, found {}",
+                operand.trim()
+            ))
+        })?;
+
+        let mut decoded = String::new();
+        let mut chars = text.chars();
+        loop {
+            match chars.next() {
+                Some('"') => break,
+                Some('\\') => match chars.next() {
+                    Some('n') => decoded.push('\n'),
+                    Some('0') => decoded.push('\0'),
+                    Some(c @ '\\') | Some(c @ '"') => decoded.push(c),
+                    Some(c) => return Err(error(format!("Unknown escape \\{} in string", c))),
+                    None => return Err(error(String::from("Unterminated string"))),
+                },
+                Some(c) => decoded.push(c),
+                None => return Err(error(String::from("Unterminated string"))),
+            }
+        }
+        let rest = chars.as_str().trim();
+        if !rest.is_empty() {
+            return Err(error(format!("Unexpected {} after string", rest)));
+        }
+
+        let bytes = decoded.into_bytes();
+        Ok(Instruction::data(bytes, self.address as u16, self.line))
+    }
+
+    fn parse_jp(&self, words: &[&str]) -> Result<String, ParseError> {
+        let (prefix, operand) = match words {
+            // Bnnn
+            [register, offset @ ..] if *register == "V0" && !offset.is_empty() => ("B", offset),
+            // 1nnn
+            _ => ("1", words),
+        };
+        let nnn = self.parse_target(&operand.join(" "))?;
+        Ok(format!("{}{:03x}", prefix, nnn))
+    }
+
+    fn parse_call(&self, words: &[&str]) -> Result<String, ParseError> {
+        // 2nnn
+        let nnn = self.parse_target(&words.join(" "))?;
+        Ok(format!("2{:03x}", nnn))
+    }
+
+    // 00FD, only known to SCHIP and later interpreters
+    fn parse_exit(&self) -> Result<String, ParseError> {
+        if self.config.mode < ChipMode::SuperChip {
+            return Err(ParseError {
+                line: self.line,
+                msg: String::from("EXIT requires SUPER-CHIP mode, use HALT instead"),
+            });
+        }
+        Ok(String::from("00FD"))
+    }
+
+    // Error unless the target interpreter is at least `mode`
+    fn require_mode(&self, mnemonic: &str, mode: ChipMode) -> Result<(), ParseError> {
+        if self.config.mode >= mode {
+            return Ok(());
+        }
+        let name = match mode {
+            ChipMode::XoChip => "XO-CHIP",
+            _ => "SUPER-CHIP",
+        };
+        Err(ParseError {
+            line: self.line,
+            msg: format!("{} requires {} mode", mnemonic, name),
+        })
+    }
+
+    // Operandless SCHIP instruction: SCR (00FB), SCL (00FC), LOW (00FE) or HIGH (00FF)
+    fn parse_schip(&self, mnemonic: &str, opcode: &str) -> Result<String, ParseError> {
+        self.require_mode(mnemonic, ChipMode::SuperChip)?;
+        Ok(String::from(opcode))
+    }
+
+    // 00Cn, scroll the display down n pixels
+    fn parse_scd(&self, word: &str) -> Result<String, ParseError> {
+        self.require_mode("SCD", ChipMode::SuperChip)?;
+        let n = self.parse_nibble(word)?;
+        Ok(format!("00C{:x}", n))
+    }
+
+    // 00Dn, scroll the display up n pixels (XO-CHIP)
+    fn parse_scu(&self, word: &str) -> Result<String, ParseError> {
+        self.require_mode("SCU", ChipMode::XoChip)?;
+        let n = self.parse_nibble(word)?;
+        Ok(format!("00D{:x}", n))
+    }
+
+    // Fn01, select the drawing planes with the bitmask n, one bit for each of the 4 display
+    // planes (XO-CHIP)
+    fn parse_plane(&self, word: &str) -> Result<String, ParseError> {
+        self.require_mode("PLANE", ChipMode::XoChip)?;
+        let n = self.check_range(self.parse_digit(word), word, "plane mask", 0xF)?;
+        Ok(format!("F{:x}01", n))
+    }
+
+    // F002, load the audio pattern from memory at I (XO-CHIP)
+    fn parse_audio(&self) -> Result<String, ParseError> {
+        self.require_mode("AUDIO", ChipMode::XoChip)?;
+        Ok(String::from("F002"))
+    }
+
+    // Fx3A, set the audio pitch to Vx (XO-CHIP)
+    fn parse_pitch(&self, word: &str) -> Result<String, ParseError> {
+        self.require_mode("PITCH", ChipMode::XoChip)?;
+        match self.parse_register(word) {
+            Some(x) => Ok(format!("F{:x}3A", x)),
+            None => Err(ParseError {
+                line: self.line,
+                msg: format!("Unable to parse PITCH instruction {}", word),
+            }),
+        }
+    }
+
+    // Register range `Vx..Vy` of the XO-CHIP save/load instructions, in either order
+    fn parse_register_range(&self, word: &str) -> Option<(u16, u16)> {
+        let (first, last) = word.split_once("..")?;
+        let x = self.parse_register(&first.to_ascii_uppercase())?;
+        let y = self.parse_register(&last.to_ascii_uppercase())?;
+        Some((x, y))
+    }
+
+    // Portable halt: EXIT where available, else a jump to itself
+    fn parse_halt(&self) -> String {
+        if self.config.mode < ChipMode::SuperChip {
+            format!("1{:03x}", self.address)
+        } else {
+            String::from("00FD")
+        }
+    }
+
+    fn parse_sne(&self, words: &[&str]) -> Result<String, ParseError> {
+        let regs: Vec<Option<u16>> = words.iter().map(|word| self.parse_register(word)).collect();
+        match (regs[0], regs[1]) {
+            (Some(x), Some(y)) => Ok(format!("9{:x}{:x}0", x, y)),
+            (Some(x), None) => {
+                let kk = self.parse_byte(words[1])?;
+                Ok(format!("4{:x}{:02x}", x, kk))
+            }
+            _ => Err(ParseError {
+                line: self.line,
+                msg: format!("Unable to parse SNE Vx, Vy instruction {}", words.join(" ")),
+            }),
+        }
+    }
+
+    fn parse_se(&self, words: &[&str]) -> Result<String, ParseError> {
+        let regs: Vec<Option<u16>> = words.iter().map(|word| self.parse_register(word)).collect();
+        match (regs[0], regs[1]) {
+            (Some(x), Some(y)) => Ok(format!("5{:x}{:x}0", x, y)),
+            (Some(x), None) => {
+                let kk = self.parse_byte(words[1])?;
+                Ok(format!("3{:x}{:02x}", x, kk))
+            }
+            _ => Err(ParseError {
+                line: self.line,
+                msg: format!("Unable to parse SE Vx, Vy instruction {}", words.join(" ")),
+            }),
+        }
+    }
+
+    fn parse_ld(&self, words: &[&str]) -> Result<String, ParseError> {
+        match words[0] {
+            "I" | "[I]" => {
+                // 5xy2 [I], Vx..Vy
+                if let Some((x, y)) = self.parse_register_range(words[1]) {
+                    self.require_mode("LD [I], Vx..Vy", ChipMode::XoChip)?;
+                    return Ok(format!("5{:x}{:x}2", x, y));
+                }
+                match self.parse_register(words[1]) {
+                    // Fx55 I, Vx
+                    Some(x) => Ok(format!("F{:x}55", x)),
+                    None => {
+                        // Annn I, addr
+                        let nnn = self.parse_addr(&words[1..].join(" "))?;
+                        Ok(format!("A{:03x}", nnn))
+                    }
+                }
+            }
+            "DT" => {
+                match self.parse_register(words[1]) {
+                    // Fx15 Dt, Vx
+                    Some(x) => Ok(format!("F{:x}15", x)),
+                    None => Err(ParseError {
+                        line: self.line,
+                        msg: format!("Unable to parse LD DT instruction {}", words.join(" ")),
+                    }),
+                }
+            }
+            "ST" => {
+                match self.parse_register(words[1]) {
+                    // Fx18 ST, Vx
+                    Some(x) => Ok(format!("F{:x}18", x)),
+                    None => Err(ParseError {
+                        line: self.line,
+                        msg: format!("Unable to parse LD ST instruction {}", words.join(" ")),
+                    }),
+                }
+            }
+            "F" => {
+                match self.parse_register(words[1]) {
+                    // Fx29 F, Vx
+                    Some(x) => Ok(format!("F{:x}29", x)),
+                    None => Err(ParseError {
+                        line: self.line,
+                        msg: format!("Unable to parse LD F instruction {}", words.join(" ")),
+                    }),
+                }
+            }
+            "B" => {
+                match self.parse_register(words[1]) {
+                    // Fx33 B, Vx
+                    Some(x) => Ok(format!("F{:x}33", x)),
+                    None => Err(ParseError {
+                        line: self.line,
+                        msg: format!("Unable to parse LD B instruction {}", words.join(" ")),
+                    }),
+                }
+            }
+            range if range.contains("..") => {
+                match (
+                    self.parse_register_range(range),
+                    self.reserved_word(words[1]),
+                ) {
+                    // 5xy3 Vx..Vy, [I]
+                    (Some((x, y)), Some("I")) => {
+                        self.require_mode("LD Vx..Vy, [I]", ChipMode::XoChip)?;
+                        Ok(format!("5{:x}{:x}3", x, y))
+                    }
+                    _ => Err(ParseError {
+                        line: self.line,
+                        msg: format!("Unable to parse LD Vx..Vy instruction {}", words.join(" ")),
+                    }),
+                }
+            }
+            _ => {
+                if let Some(x) = self.parse_register(words[0]) {
+                    // Reserved operands are matched before registers and numbers (and any
+                    // future symbols), so a source such as K is never read as a symbol
+                    match self.reserved_word(words[1]) {
+                        // Fx07 Vx, Dt
+                        Some("DT") => Ok(format!("F{:x}07", x)),
+                        // Fx0A Vx, K
+                        Some("K") => Ok(format!("F{:x}0A", x)),
+                        // Fx65 Vx, I
+                        Some("I") => Ok(format!("F{:x}65", x)),
+                        Some(reserved) => Err(ParseError {
+                            line: self.line,
+                            msg: format!("`{}` can't be the source of LD Vx", reserved),
+                        }),
+                        None => match self.parse_register(words[1]) {
+                            // 8xy0 Vx, Vy
+                            Some(y) => Ok(format!("8{:x}{:x}0", x, y)),
+                            None => {
+                                // 6xkk Vx, byte
+                                let kk = self.parse_byte(words[1])?;
+                                Ok(format!("6{:x}{:02x}", x, kk))
+                            }
+                        },
+                    }
+                } else {
+                    Err(ParseError {
+                        line: self.line,
+                        msg: format!("Unable to parse LD Vx instruction {}", words.join(" ")),
+                    })
+                }
+            }
+        }
+    }
+
+    fn parse_or(&self, words: &[&str]) -> Result<String, ParseError> {
+        let regs: Vec<Option<u16>> = words.iter().map(|word| self.parse_register(word)).collect();
+        match (regs[0], regs[1]) {
+            (Some(x), Some(y)) => Ok(format!("8{:x}{:x}1", x, y)),
+            _ => Err(ParseError {
+                line: self.line,
+                msg: format!("Unable to parse OR instruction {}", words.join(" ")),
+            }),
+        }
+    }
+
+    fn parse_and(&self, words: &[&str]) -> Result<String, ParseError> {
+        let regs: Vec<Option<u16>> = words.iter().map(|word| self.parse_register(word)).collect();
+        match (regs[0], regs[1]) {
+            (Some(x), Some(y)) => Ok(format!("8{:x}{:x}2", x, y)),
+            _ => Err(ParseError {
+                line: self.line,
+                msg: format!("Unable to parse AND instruction {}", words.join(" ")),
+            }),
+        }
+    }
+
+    fn parse_xor(&self, words: &[&str]) -> Result<String, ParseError> {
+        let regs: Vec<Option<u16>> = words.iter().map(|word| self.parse_register(word)).collect();
+        match (regs[0], regs[1]) {
+            (Some(x), Some(y)) => Ok(format!("8{:x}{:x}3", x, y)),
+            _ => Err(ParseError {
+                line: self.line,
+                msg: format!("Unable to parse XOR instruction {}", words.join(" ")),
+            }),
+        }
+    }
+
+    fn parse_rnd(&self, words: &[&str]) -> Result<String, ParseError> {
+        let x = self.parse_register(words[0]);
+        let kk = self.parse_byte(words[1])?;
+        match x {
+            Some(x) => Ok(format!("C{:x}{:02x}", x, kk)),
+            _ => Err(ParseError {
+                line: self.line,
+                msg: format!("Unable to parse XOR instruction {}", words.join(" ")),
+            }),
+        }
+    }
+
+    fn parse_drw(&self, words: &[&str]) -> Result<String, ParseError> {
+        let regs: Vec<Option<u16>> = words[0..=1]
+            .iter()
+            .map(|word| self.parse_register(word))
+            .collect();
+        let n = self.parse_nibble(words[2])?;
+        // Dxy0 draws a 16x16 sprite on SCHIP, classic interpreters don't know it
+        if n == 0 && self.config.mode < ChipMode::SuperChip {
+            return Err(ParseError {
+                line: self.line,
+                msg: String::from(
+                    "DRW with a height of 0 draws a 16x16 sprite, which requires SUPER-CHIP mode",
+                ),
+            });
+        }
+        match (regs[0], regs[1]) {
+            (Some(x), Some(y)) => Ok(format!("D{:x}{:x}{:x}", x, y, n)),
+            _ => Err(ParseError {
+                line: self.line,
+                msg: format!("Unable to parse DRW instruction {}", words.join(" ")),
+            }),
+        }
+    }
+
+    fn parse_skp(&self, words: &[&str]) -> Result<String, ParseError> {
+        let reg: Vec<Option<u16>> = words.iter().map(|word| self.parse_register(word)).collect();
+        match reg[0] {
+            Some(x) => Ok(format!("E{:x}9E", x)),
+            _ => Err(ParseError {
+                line: self.line,
+                msg: format!("Unable to parse XOR instruction {}", words.join(" ")),
+            }),
+        }
+    }
+
+    fn parse_sknp(&self, word: &str) -> Result<String, ParseError> {
+        let reg = self.parse_register(word);
+        match reg {
+            Some(x) => Ok(format!("E{:x}A1", x)),
+            _ => Err(ParseError {
+                line: self.line,
+                msg: format!("Unable to parse XOR instruction {}", word),
+            }),
+        }
+    }
+
+    fn parse_add(&self, words: &[&str]) -> Result<String, ParseError> {
+        match words[0] {
+            // Fx1E
+            "I" => match self.parse_register(words[1]) {
+                Some(x) => Ok(format!("F{:x}1E", x)),
+                _ => Err(ParseError {
+                    line: self.line,
+                    msg: format!("Unable to parse ADD I, Vx instruction {}", words.join(" ")),
+                }),
+            },
+            _ => {
+                let regs: Vec<Option<u16>> =
+                    words.iter().map(|word| self.parse_register(word)).collect();
+                match (regs[0], regs[1]) {
+                    // 8xy4
+                    (Some(x), Some(y)) => Ok(format!("8{:x}{:x}4", x, y)),
+                    // 7xkk
+                    (Some(x), None) => {
+                        let kk = self.parse_byte(words[1])?;
+                        Ok(format!("7{:x}{:02x}", x, kk))
+                    }
+                    _ => Err(ParseError {
+                        line: self.line,
+                        msg: format!("Unable to parse ADD Vx, Vy instruction {}", words.join(" ")),
+                    }),
+                }
+            }
+        }
+    }
+
+    fn parse_sub(&self, words: &[&str]) -> Result<String, ParseError> {
+        let regs: Vec<Option<u16>> = words.iter().map(|word| self.parse_register(word)).collect();
+        match (regs[0], regs[1]) {
+            (Some(x), Some(y)) => Ok(format!("8{:x}{:x}5", x, y)),
+            _ => Err(ParseError {
+                line: self.line,
+                msg: format!("Unable to parse SUB instruction {}", words.join(" ")),
+            }),
+        }
+    }
+
+    fn parse_shr(&self, words: &[&str]) -> Result<String, ParseError> {
+        let regs: Vec<Option<u16>> = words.iter().map(|word| self.parse_register(word)).collect();
+        // Vy is optional, as written by the disassembler
+        match (regs[0], regs.get(1).copied().unwrap_or(Some(0))) {
+            (Some(x), Some(y)) => Ok(format!("8{:x}{:x}6", x, y)),
+            _ => Err(ParseError {
+                line: self.line,
+                msg: format!("Unable to parse SHR instruction {}", words.join(" ")),
+            }),
+        }
+    }
+
+    fn parse_subn(&self, words: &[&str]) -> Result<String, ParseError> {
+        let regs: Vec<Option<u16>> = words.iter().map(|word| self.parse_register(word)).collect();
+        match (regs[0], regs[1]) {
+            (Some(x), Some(y)) => Ok(format!("8{:x}{:x}7", x, y)),
+            _ => Err(ParseError {
+                line: self.line,
+                msg: format!("Unable to parse SUBN instruction {}", words.join(" ")),
+            }),
+        }
+    }
+
+    fn parse_shl(&self, words: &[&str]) -> Result<String, ParseError> {
+        let regs: Vec<Option<u16>> = words.iter().map(|word| self.parse_register(word)).collect();
+        // Vy is optional, as written by the disassembler
+        match (regs[0], regs.get(1).copied().unwrap_or(Some(0))) {
+            (Some(x), Some(y)) => Ok(format!("8{:x}{:x}E", x, y)),
+            _ => Err(ParseError {
+                line: self.line,
+                msg: format!("Unable to parse SHL instruction {}", words.join(" ")),
+            }),
+        }
+    }
+
+    fn write_file(&self) -> std::io::Result<String> {
+        let file_name = match &self.output_path {
+            // A directory receives the file under its default name
+            Some(output_path)
+                if Path::new(output_path).is_dir() && !self.source_path.is_empty() =>
+            {
+                let default_path = self.parse_path();
+                let default_name = Path::new(&default_path).file_name().unwrap_or_default();
+                Path::new(output_path)
+                    .join(default_name)
+                    .to_string_lossy()
+                    .into_owned()
+            }
+            Some(output_path) => output_path.clone(),
+            None if self.source_path.is_empty() => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "No output file for source without a path, use -o to set one",
+                ))
+            }
+            None => self.parse_path(),
+        };
+        let output_path = Path::new(&file_name);
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(output_path)
+            .map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("Couldn't create file {}: {}", file_name, e),
+                )
+            })?;
+        self.write_to(&mut file)?;
+        Ok(file_name)
+    }
+
+    // Write the last assembled ROM, in the configured output format, to any writer such as a
+    // file, stdout or an in-memory buffer
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let bytes = self.rom_bytes();
+        match self.config.output_format {
+            RomFormat::Binary => w.write_all(&bytes),
+            RomFormat::Hex => writeln!(w, "{}", hex::encode(bytes)),
+            RomFormat::IntelHex => {
+                let start_address = self.config.start_address as u16;
+                w.write_all(to_intel_hex(&bytes, start_address).as_bytes())
+            }
+        }
+    }
+
+    fn parse_path(&self) -> String {
+        let file_name: Vec<_> = self.source_path.split(".chasm").collect();
+        let extension = match self.config.output_format {
+            RomFormat::Binary => "ch8",
+            RomFormat::Hex => "hex",
+            RomFormat::IntelHex => "ihx",
+        };
+        format!("{}_a.{}", file_name[0], extension)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Lines of random words, to catch operands indexed without checking the arity
+    fn random_sources(count: usize) -> Vec<String> {
+        let words = [
+            "JP", "CALL", "SE", "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN",
+            "SHL", "RND", "DRW", "SKP", "SKNP", "DB", "DW", "DA", "RET", "CLS", "EQU", ".org",
+            "V0", "VF", "V99", "I", "[I]", "DT", "ST", "K", "F", "B", ",", "0b", "0x", "<", ">",
+            "$", "4096", "65535", "99999", "'", "\"", ";", "//", ":", "é", "V", "",
+        ];
+        let mut seed: u64 = 0x2545_F491_4F6C_DD1D;
+        let mut next = move || {
+            seed = seed
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (seed >> 33) as usize
+        };
+        (0..count)
+            .map(|_| {
+                let mut source = String::new();
+                for _ in 0..next() % 8 {
+                    source.push_str(words[next() % words.len()]);
+                    source.push(match next() % 3 {
+                        0 => ' ',
+                        1 => ',',
+                        _ => '\n',
+                    });
+                }
+                source
+            })
+            .collect()
+    }
+
+    #[test]
+    fn assembles_to_bytes() {
+        let source = "start:\n  LD V0, 0x0A\n  ADD V0, 1\n  JP start\n";
+        let mut assembler = Assembler::from_source(source.to_string());
+        assert_eq!(
+            assembler.assemble().unwrap(),
+            vec![0x60, 0x0A, 0x70, 0x01, 0x12, 0x00]
+        );
+        assert_eq!(assembler.instructions().len(), 3);
+
+        let errors = Assembler::from_source("CLS\nJP nowhere\n".to_string())
+            .assemble()
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 2);
+    }
+
+    #[test]
+    fn fills_gaps_with_the_fill_byte() {
+        let source = "CLS\n.org 0x206\nRET\n";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![0x00, 0xE0, 0x00, 0x00, 0x00, 0x00, 0x00, 0xEE]
+        );
+        let rom = Assembler::builder()
+            .source(source.to_string())
+            .fill_byte(0xFF)
+            .assemble()
+            .unwrap();
+        assert_eq!(rom, vec![0x00, 0xE0, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0xEE]);
+    }
+
+    #[test]
+    fn sizes_data_with_forward_references() {
+        let source = "DW later, later\nlater: CLS\n";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![0x02, 0x04, 0x02, 0x04, 0x00, 0xE0]
+        );
+        let source = "DW first, second\nfirst: CLS\nsecond: RET\nJP second\n";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![0x02, 0x04, 0x02, 0x06, 0x00, 0xE0, 0x00, 0xEE, 0x12, 0x06]
+        );
+        let source = "JP later\nDA \"HI\"\nlater: DW 0x1234\n";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![0x12, 0x04, b'H', b'I', 0x12, 0x34]
+        );
+    }
+
+    #[test]
+    fn selects_bytes_of_labels() {
+        assert_eq!(
+            assemble("loop: CLS\nDB >loop, <loop, <$\n").unwrap(),
+            vec![0x00, 0xE0, 0x02, 0x00, 0x02]
+        );
+    }
+
+    #[test]
+    fn bounds_only_label_relative_targets() {
+        assert_eq!(
+            assemble("JP 5\nCALL 256\n").unwrap(),
+            vec![0x10, 0x05, 0x21, 0x00]
+        );
+        assert!(assemble("loop: JP loop - 0x180\n").is_err());
+    }
+
+    #[test]
+    fn offsets_labels_backwards() {
+        assert_eq!(
+            assemble("CLS\nloop: CLS\nJP loop - 2\nCALL loop - 2 + 4\n").unwrap(),
+            vec![0x00, 0xE0, 0x00, 0xE0, 0x12, 0x00, 0x22, 0x04]
+        );
+        assert!(assemble("start: JP start - 2\n").is_err());
+        assert!(assemble("start: JP start - 600\n").is_err());
+    }
+
+    #[test]
+    fn selects_any_of_the_four_planes() {
+        let xo_chip = |source: &str| {
+            Assembler::builder()
+                .source(source.to_string())
+                .mode(ChipMode::XoChip)
+                .assemble()
+        };
+        assert_eq!(xo_chip("PLANE 15").unwrap(), vec![0xFF, 0x01]);
+        assert!(xo_chip("PLANE 16").is_err());
+    }
+
+    #[test]
+    fn rejects_signed_literals() {
+        for source in &[
+            "LD V0, +5",
+            "LD V0, -5",
+            "LD V0, 0x+5",
+            "LD V0, 0b+1",
+            "DB +1",
+        ] {
+            assert!(assemble(source).is_err(), "{} assembled", source);
+        }
+        assert_eq!(assemble("LD V0, 0b101").unwrap(), vec![0x60, 0x05]);
+    }
+
+    #[test]
+    fn reports_values_too_large_as_out_of_range() {
+        let errors = assemble("X EQU 0x10000\n").unwrap_err();
+        assert!(
+            errors[0].msg.starts_with("Out of range constant X"),
+            "{}",
+            errors[0]
+        );
+        let errors = assemble("LD V0, 70000\n").unwrap_err();
+        assert!(
+            errors[0].msg.starts_with("Out of range byte"),
+            "{}",
+            errors[0]
+        );
+    }
+
+    #[test]
+    fn reports_instructions_past_the_end_of_memory() {
+        assert_eq!(assemble("LD I, 4095\n").unwrap(), vec![0xAF, 0xFF]);
+        assert!(assemble("LD I, 4096\n").is_err());
+
+        let errors = assemble(".org 0xFFF\nCLS\n").unwrap_err();
+        assert_eq!(
+            errors[0].msg,
+            "2-byte instruction at 0xfff runs past the end of memory at 0xfff"
+        );
+        assert!(assemble(".org 0x1000\nCLS\n").is_err());
+        let errors = assemble(".org 0xFFE\nCLS\nCLS\n").unwrap_err();
+        assert_eq!(
+            errors[0].msg,
+            "Address 0x1000 is out of range, memory ends at 0xfff"
+        );
+        // The gap up to 0xFFF is filled, so only the last byte is the DB
+        let rom = assemble(".org 0xFFF\nDB 1\n").unwrap();
+        assert_eq!((rom.len(), rom.last()), (0xFFF - 0x200 + 1, Some(&1)));
+    }
+
+    #[test]
+    fn suggests_the_closest_mnemonic() {
+        let error = |source: &str| assemble(source).unwrap_err()[0].msg.clone();
+        assert_eq!(
+            error("JMP 0x200"),
+            "Unknown mnemonic 'JMP'; did you mean 'JP'?"
+        );
+        assert_eq!(
+            error("CAL 0x200"),
+            "Unknown mnemonic 'CAL'; did you mean 'CALL'?"
+        );
+        // Words that happen to be hex digits aren't taken as raw opcodes
+        for source in &["DEAD", "BEEF", "00FD", "D010"] {
+            assert!(error(source).starts_with("Unknown mnemonic"), "{}", source);
+        }
+    }
+
+    #[test]
+    fn random_input_never_panics() {
+        for source in random_sources(5_000) {
+            let _ = assemble(&source);
+        }
+    }
+
+    #[test]
+    fn keeps_bracketed_operands_together() {
+        let assembler = Assembler::from_source(String::new());
+        assert_eq!(
+            assembler.split_words("LD [ I ], V5"),
+            vec!["LD", "[I]", "V5"]
+        );
+        assert_eq!(assemble("LD [I], V5\n").unwrap(), vec![0xF5, 0x55]);
+        assert_eq!(assemble("LD V5, [ I ]\n").unwrap(), vec![0xF5, 0x65]);
+    }
+
+    #[test]
+    fn names_registers_in_hex_or_decimal() {
+        assert_eq!(assemble("XOR VF, VA\n").unwrap(), vec![0x8F, 0xA3]);
+        assert_eq!(assemble("LD V15, V10\n").unwrap(), vec![0x8F, 0xA0]);
+        assert!(assemble("LD V16, 1\n").is_err());
+        assert!(assemble("LD VG, 1\n").is_err());
+    }
+
+    #[test]
+    fn rejects_signed_register_names() {
+        for source in &["LD V+1, 5", "LD V-1, 5", "LD V+10, 5", "LD V 1, 5"] {
+            assert!(assemble(source).is_err(), "{} assembled", source);
+        }
+        assert_eq!(assemble("LD V1, 5").unwrap(), vec![0x61, 0x05]);
+        assert_eq!(assemble("LD V10, 5").unwrap(), vec![0x6A, 0x05]);
+        assert_eq!(assemble("LD VA, 5").unwrap(), vec![0x6A, 0x05]);
+    }
+
+    #[test]
+    fn maps_source_lines_to_addresses() {
+        let source = "CLS\n\nloop: LD V0, 5\n; comment\nJP loop\n";
+        let (bytes, line_map) = Assembler::from_source(source.to_string())
+            .assemble_with_line_map()
+            .unwrap();
+        assert_eq!(bytes, vec![0x00, 0xE0, 0x60, 0x05, 0x12, 0x02]);
+        assert_eq!(line_map, vec![(1, 0x200), (3, 0x202), (5, 0x204)]);
+    }
+
+    #[test]
+    fn assembles_binary_data_bytes() {
+        assert_eq!(
+            assemble("DB 0b10000001, 0b01000010\n").unwrap(),
+            vec![0x81, 0x42]
+        );
+        assert_eq!(assemble("DB 0b11110000\n").unwrap(), vec![0xF0]);
+        assert!(assemble("DB 0b100000000\n").is_err());
+    }
+
+    #[test]
+    fn rejects_reserved_symbol_names() {
+        for source in &[
+            "DT EQU 5\n",
+            "dt EQU 5\n",
+            "I:\nCLS\n",
+            "HF: CLS\n",
+            "V3 EQU 1\n",
+        ] {
+            let errors = assemble(source).unwrap_err();
+            assert_eq!(errors.len(), 1, "{}", source);
+        }
+        let errors = assemble("DT EQU 5\n").unwrap_err();
+        assert_eq!(errors[0].msg, "`DT` is a reserved operand name");
+        assert_eq!(
+            assemble("DELAY EQU 5\nLD V0, DELAY\n").unwrap(),
+            vec![0x60, 0x05]
+        );
+    }
+
+    #[test]
+    fn emits_words_big_endian() {
+        assert_eq!(assemble("DW 0x1234\n").unwrap(), vec![0x12, 0x34]);
+        assert_eq!(
+            assemble("DW 0xABCD, 5\n").unwrap(),
+            vec![0xAB, 0xCD, 0x00, 0x05]
+        );
+        assert!(assemble("DW 0x10000\n").is_err());
+    }
+
+    #[test]
+    fn strips_both_comment_styles() {
+        let plain = "start:\nLD V0, 1\nJP start\n";
+        let commented = "// setup\nstart: // entry\nLD V0, 1 ; one\nJP start// loop\n";
+        assert_eq!(assemble(commented).unwrap(), assemble(plain).unwrap());
+    }
+
+    #[test]
+    fn encodes_assembled_instructions() {
+        let mut assembler = Assembler::from_source("CLS\nJP 0x204\nRET\n".to_string());
+        assembler.assemble().unwrap();
+        let words: Vec<u16> = assembler
+            .instructions()
+            .iter()
+            .map(Instruction::encoded_u16)
+            .collect();
+        assert_eq!(words, vec![0x00E0, 0x1204, 0x00EE]);
+    }
+
+    #[test]
+    fn resolves_reserved_load_sources() {
+        assert_eq!(
+            assemble("LD V0, DT\nLD V0, K\nLD V0, I\nLD V1, [I]\nLD V2, 5\n").unwrap(),
+            vec![0xF0, 0x07, 0xF0, 0x0A, 0xF0, 0x65, 0xF1, 0x65, 0x62, 0x05]
+        );
+        // A symbol can't take the name of a source, so `LD V0, K` always waits for a key
+        assert!(assemble("K EQU 5\nLD V0, K\n").is_err());
+        let errors = assemble("LD V0, ST\n").unwrap_err();
+        assert_eq!(errors[0].msg, "`ST` can't be the source of LD Vx");
+    }
+
+    #[test]
+    fn assembles_a_string_source() {
+        let mut assembler = Assembler::from_source(String::from("CLS\nRET"));
+        assert!(assembler.source_path.is_empty());
+        assert_eq!(assembler.assemble().unwrap(), vec![0x00, 0xE0, 0x00, 0xEE]);
+
+        // Writing the ROM only needs the output path
+        let output_path = std::env::temp_dir().join("chiprs_from_source.ch8");
+        assembler.set_output_path(output_path.to_str().unwrap().to_string());
+        assembler.run();
+        assert_eq!(
+            fs::read(&output_path).unwrap(),
+            vec![0x00, 0xE0, 0x00, 0xEE]
+        );
+    }
+
+    #[test]
+    fn evaluates_every_address_form() {
+        assert_eq!(
+            assemble("JP 0x300\nCALL 768\nJP 0b1100000000\n").unwrap(),
+            vec![0x13, 0x00, 0x23, 0x00, 0x13, 0x00]
+        );
+        let source = "CLS\nloop:\nJP loop\nCALL loop + 256\nJP $\nCALL . - 0x2\nJP V0, loop\n";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![0x00, 0xE0, 0x12, 0x02, 0x23, 0x02, 0x12, 0x06, 0x22, 0x06, 0xB2, 0x02]
+        );
+        assert!(assemble("CALL nowhere + 2\n").is_err());
+    }
+
+    #[test]
+    fn assembles_to_a_hex_string() {
+        assert_eq!(assemble_hex("LD V0, 5").unwrap(), "6005");
+        assert_eq!(
+            Assembler::from_source(String::from("CLS\nLD VA, 0xBC"))
+                .assemble_hex()
+                .unwrap(),
+            "00e06abc"
+        );
+    }
+
+    #[test]
+    fn checks_operand_ranges_by_context() {
+        for source in &[
+            "JP 4096",
+            "LD I, 0x1000",
+            "LD V0, 256",
+            "ADD V0, 300",
+            "SE V0, 256",
+            "RND V0, 256",
+            "DRW V0, V1, 16",
+        ] {
+            assert!(assemble(source).is_err(), "{} assembled", source);
+        }
+        assert_eq!(
+            assemble("DRW V0, V1, 15\nLD V0, 255\nJP 4095\n").unwrap(),
+            vec![0xD0, 0x1F, 0x60, 0xFF, 0x1F, 0xFF]
+        );
+        let errors = assemble("LD V0, 256\nDRW V0, V1, 16\n").unwrap_err();
+        assert_eq!(errors[0].msg, "Out of range byte 256 (0x100), max is 0xff");
+        assert_eq!(errors[1].msg, "Out of range nibble 16 (0x10), max is 0xf");
+    }
+
+    #[test]
+    fn resolves_labels() {
+        assert_eq!(
+            assemble("loop: CLS\nJP loop\nsprite:\n   DB 0xFF\nLD I, sprite\n").unwrap(),
+            vec![0x00, 0xE0, 0x12, 0x00, 0xFF, 0xA2, 0x04]
+        );
+        let errors = assemble("loop:\nLD I, sprite\nJP loop\n").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 2);
+        assert_eq!(errors[0].msg, "Undefined label `sprite`");
+    }
+
+    #[test]
+    fn resolves_forward_and_backward_references_alike() {
+        let rom = assemble("JP end\nCLS\nend:\nCLS\nJP end\nCALL later\nlater: RET\n").unwrap();
+        assert_eq!(
+            rom,
+            vec![0x12, 0x04, 0x00, 0xE0, 0x00, 0xE0, 0x12, 0x04, 0x22, 0x0A, 0x00, 0xEE]
+        );
+        assert_eq!(rom[0..2], rom[6..8]);
+        // Errors after a forward reference keep their own line
+        let errors = assemble("JP end\nFOO\nend: CLS\n").unwrap_err();
+        assert_eq!(errors[0].line, 2);
+    }
+
+    #[test]
+    fn halts_in_each_mode() {
+        assert_eq!(assemble("HALT\n").unwrap(), vec![0x12, 0x00]);
+        assert_eq!(
+            assemble("CLS\nHALT\n").unwrap(),
+            vec![0x00, 0xE0, 0x12, 0x02]
+        );
+        // EXIT has no classic encoding
+        assert!(assemble("EXIT\n").is_err());
+
+        let rom = Assembler::builder()
+            .source(String::from("HALT\nEXIT\n"))
+            .mode(ChipMode::SuperChip)
+            .assemble()
+            .unwrap();
+        assert_eq!(rom, vec![0x00, 0xFD, 0x00, 0xFD]);
+    }
+
+    #[test]
+    fn skips_comments() {
+        let mut assembler = Assembler::from_source(String::from("; hi\nLD V0, 5 ; init\n;\nCLS\n"));
+        assert_eq!(assembler.assemble().unwrap(), vec![0x60, 0x05, 0x00, 0xE0]);
+        assert_eq!(assembler.instructions()[1].address, 0x202);
+        // Inside a string, `;` is just a character
+        assert_eq!(assemble("DA \"a;b\" ; note\n").unwrap(), b"a;b".to_vec());
+    }
+
+    #[test]
+    fn skips_blank_lines() {
+        let mut assembler = Assembler::from_source(String::from("\n  \nLD V0, 5\n\t\n,\nCLS\n\n"));
+        assert_eq!(
+            assembler.assemble().unwrap(),
+            assemble("LD V0, 5\nCLS\n").unwrap()
+        );
+        let lines: Vec<usize> = assembler
+            .instructions()
+            .iter()
+            .map(|inst| inst.line)
+            .collect();
+        assert_eq!(lines, vec![3, 6]);
+    }
+
+    #[test]
+    fn parses_hex_literals() {
+        assert_eq!(
+            assemble("LD V1, 0x2A\nLD V2, 0X2a\nJP 0x200\nDRW V0, V1, 0xF\n").unwrap(),
+            vec![0x61, 0x2A, 0x62, 0x2A, 0x12, 0x00, 0xD0, 0x1F]
+        );
+        let errors = assemble("LD V1, 0x10000\n").unwrap_err();
+        assert_eq!(
+            errors[0].msg,
+            "Out of range byte 0x10000 (0x10000), max is 0xff"
+        );
+    }
+
+    #[test]
+    fn emits_data_bytes() {
+        let mut assembler =
+            Assembler::from_source(String::from("DB 0xF0, 0x90, 0x90, 0x90, 0xF0\nCLS\n"));
+        let rom = assembler.assemble().unwrap();
+        assert_eq!(rom[..5], crate::font::FONT_SET[0]);
+        assert_eq!(rom[5..], [0x00, 0xE0]);
+        // The instruction after the sprite starts right after its 5 bytes
+        assert_eq!(assembler.instructions()[1].address, 0x205);
+    }
+
+    #[test]
+    fn keeps_the_errors_of_the_last_run() {
+        let mut assembler =
+            Assembler::from_source(String::from("CLS\nFOO V1\nLD V0, 300\nx:\nx:\n"));
+        assert!(assembler.assemble().is_err());
+        let lines: Vec<usize> = assembler.errors().iter().map(|error| error.line).collect();
+        assert_eq!(lines, vec![2, 3, 5]);
+
+        let mut assembler = Assembler::from_source(String::from("CLS\n"));
+        assembler.assemble().unwrap();
+        assert!(assembler.errors().is_empty());
+    }
+
+    #[test]
+    fn emits_string_bytes() {
+        assert_eq!(
+            assemble("DA \"HI, you; x\" ; comment\nCLS\n").unwrap(),
+            b"HI, you; x\x00\xe0".to_vec()
+        );
+        assert_eq!(
+            assemble("DA \"a\\n\\0\\\\\\\"b\"\n").unwrap(),
+            b"a\n\0\\\"b".to_vec()
+        );
+        assert_eq!(assemble("DA \"\"\nCLS\n").unwrap(), vec![0x00, 0xE0]);
+
+        let errors = assemble("CLS\nDA \"abc\nDA abc\nDA \"a\\q\"\n").unwrap_err();
+        let lines: Vec<usize> = errors.iter().map(|error| error.line).collect();
+        assert_eq!(lines, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn pads_between_org_regions() {
+        assert_eq!(
+            assemble("CLS\n.org 0x206\nRET\n.ORG 0x20A\ndata:\nDB 1\n").unwrap(),
+            vec![0x00, 0xE0, 0x00, 0x00, 0x00, 0x00, 0x00, 0xEE, 0x00, 0x00, 0x01]
+        );
+        // Moving backward over emitted bytes, past the end of memory or below the ROM start
+        let errors = assemble("CLS\nCLS\n.org 0x200\n.org 0x1000\n.org 0x100\n").unwrap_err();
+        let lines: Vec<usize> = errors.iter().map(|error| error.line).collect();
+        assert_eq!(lines, vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn substitutes_constants() {
+        let source = "WIDTH EQU 64\nSPRITE EQU data + 1\nLD V0, WIDTH\nLD I, SPRITE\nJP START\n\
+                      START EQU 0x200\ndata:\nDB WIDTH\n";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![0x60, 0x40, 0xA2, 0x07, 0x12, 0x00, 0x40]
+        );
+        // A duplicate, an undefined value, a register name and a label clashing with a constant
+        let errors =
+            assemble("A EQU 1\nA EQU 2\nBB EQU nothere\nV0 EQU 3\nA:\nLD V0, A\n").unwrap_err();
+        assert_eq!(errors.len(), 4);
+        // V followed by a constant name isn't a register
+        assert!(assemble("X1 EQU 3\nLD VX1, 1\n").is_err());
+    }
+
+    #[test]
+    fn gates_16x16_sprites_on_schip() {
+        let in_mode = |mode| {
+            Assembler::builder()
+                .source(String::from("DRW V0, V1, 0\n"))
+                .mode(mode)
+                .assemble()
+        };
+        assert_eq!(in_mode(ChipMode::SuperChip).unwrap(), vec![0xD0, 0x10]);
+        let errors = in_mode(ChipMode::Chip8).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0]
+            .msg
+            .contains("16x16 sprite, which requires SUPER-CHIP"));
+        assert_eq!(assemble("DRW V0, V1, 5\n").unwrap(), vec![0xD0, 0x15]);
+    }
+
+    #[test]
+    fn ignores_the_case_of_mnemonics_and_registers() {
+        let upper =
+            "Loop:\nCLS\nLD V0, 5\nLD VA, DT\nLD [I], VF\nLD I, Loop\nDRW V0, V1, 5\nJP Loop\n\
+                     DA \"Hi\"\n";
+        let lower =
+            "Loop:\ncls\nld v0, 5\nld va, dt\nld [i], vf\nld i, Loop\ndrw v0, v1, 5\njp Loop\n\
+                     da \"Hi\"\n";
+        let rom = assemble(upper).unwrap();
+        assert_eq!(rom.len(), 16);
+        assert_eq!(assemble(lower).unwrap(), rom);
+        // Labels stay case-sensitive
+        assert!(assemble("jp loop\nLoop:\n").is_err());
+    }
+
+    #[test]
+    fn reports_every_bad_line() {
+        let errors = assemble("CLS\nFOO\nLD V0, 256\nCLS\nJP nowhere\nRET\n").unwrap_err();
+        let lines: Vec<usize> = errors.iter().map(|error| error.line).collect();
+        assert_eq!(lines, vec![2, 3, 5]);
+        // Every message names its line
+        let report = errors.to_string();
+        for line in &[2, 3, 5] {
+            assert!(report.contains(&format!("[Line {}]", line)), "{}", report);
+        }
+
+        // and no ROM gets written
+        let output_path = std::env::temp_dir().join("chiprs_bad_lines.ch8");
+        let _ = fs::remove_file(&output_path);
+        let mut assembler = Assembler::from_source(String::from("CLS\nFOO\n"));
+        assembler.set_output_path(output_path.to_str().unwrap().to_string());
+        assembler.run();
+        assert!(!output_path.exists());
+    }
+
+    #[test]
+    fn reads_files_like_strings() {
+        let source = "start:\nLD V0, 1\nJP start\n";
+        let path = std::env::temp_dir().join("chiprs_from_file.chasm");
+        fs::write(&path, source).unwrap();
+        let mut from_file = Assembler::new(path.to_str().unwrap().to_string()).unwrap();
+        let mut from_source = Assembler::from_source(source.to_string());
+        assert_eq!(
+            from_file.assemble().unwrap(),
+            from_source.assemble().unwrap()
+        );
+    }
+
+    #[test]
+    fn assembles_char_literals() {
+        assert_eq!(assemble("LD V0, 'A'\n").unwrap(), vec![0x60, 0x41]);
+        assert_eq!(assemble("ld v1, ' ' ; space\n").unwrap(), vec![0x61, 0x20]);
+        assert_eq!(assemble("SE V2, ';'\n").unwrap(), vec![0x32, 0x3B]);
+        assert_eq!(assemble("DB 'h', ','\n").unwrap(), vec![0x68, 0x2C]);
+        assert_eq!(assemble("DA \"it's\"\n").unwrap(), b"it's".to_vec());
+
+        let errors = assemble("LD V0, 'AB'\n").unwrap_err();
+        assert!(errors[0].msg.starts_with("Invalid character literal 'AB'"));
+        assert!(assemble("LD V0, ''\n").is_err());
+    }
+
+    #[test]
+    fn fails_to_open_a_missing_file() {
+        let path = std::env::temp_dir()
+            .join("chiprs_missing")
+            .join("nothing.chasm");
+        let error = Assembler::new(path.to_str().unwrap().to_string())
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn writes_to_the_output_path() {
+        let dir = std::env::temp_dir().join("chiprs_output_path");
+        fs::create_dir_all(&dir).unwrap();
+        let source_path = std::env::temp_dir().join("chiprs_output_path.chasm");
+        fs::write(&source_path, "CLS\n").unwrap();
+        let source_path = source_path.to_str().unwrap().to_string();
+
+        // A file path is used as is, a directory gets the default file name
+        let file_path = dir.join("explicit.ch8");
+        for output_path in &[file_path.clone(), dir.clone()] {
+            let mut assembler = Assembler::new(source_path.clone()).unwrap();
+            assembler.set_output_path(output_path.to_str().unwrap().to_string());
+            assembler.run();
+        }
+        assert_eq!(fs::read(&file_path).unwrap(), vec![0x00, 0xE0]);
+        assert_eq!(
+            fs::read(dir.join("chiprs_output_path_a.ch8")).unwrap(),
+            vec![0x00, 0xE0]
+        );
+
+        // Without a source path there's no default name to fall back on
+        assert!(Assembler::from_source(String::from("CLS\n"))
+            .write_file()
+            .is_err());
+    }
+
+    #[test]
+    fn summarizes_errors() {
+        let errors = assemble("CLS\nCLS\nFOO\nLD V0, 300\nCLS\nJP nowhere\n").unwrap_err();
+        assert_eq!(
+            errors.summary(),
+            "assembly failed: 3 errors (first at line 3)"
+        );
+        let report = errors.to_string();
+        assert!(report.starts_with("assembly failed: 3 errors (first at line 3)\n[Line 3]"));
+        assert_eq!(report.lines().count(), 4);
+        assert_eq!(
+            assemble("FOO").unwrap_err().summary(),
+            "assembly failed: 1 error (first at line 1)"
+        );
+    }
+
+    #[test]
+    fn assembles_at_another_base_address() {
+        let source = "start:\nCLS\nJP start\nLD I, data\ndata:\nDB 1\n";
+        let rom = Assembler::builder()
+            .source(source.to_string())
+            .start_address(0x600)
+            .assemble()
+            .unwrap();
+        assert_eq!(rom, vec![0x00, 0xE0, 0x16, 0x00, 0xA6, 0x06, 0x01]);
+
+        let (_, line_map) = Assembler::builder()
+            .source(String::from("CLS\nCLS\n"))
+            .start_address(0x600)
+            .build()
+            .assemble_with_line_map()
+            .unwrap();
+        assert_eq!(line_map, vec![(1, 0x600), (2, 0x602)]);
+    }
+
+    #[test]
+    fn writes_the_symbol_map_by_address() {
+        let mut assembler = Assembler::from_source(String::from(
+            "start:\nCLS\nJP sprite\nsprite:\nDB 0xF0, 0x90\n",
+        ));
+        assembler.assemble().unwrap();
+        let path = std::env::temp_dir().join("chiprs_symbols.sym");
+        assembler.write_symbol_map(path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "start = 0x200\nsprite = 0x204\n"
+        );
+    }
+
+    #[test]
+    fn accepts_the_configured_register_style() {
+        let in_style = |source: &str, style| {
+            Assembler::builder()
+                .source(source.to_string())
+                .register_style(style)
+                .assemble()
+                .ok()
+        };
+        assert_eq!(
+            in_style("LD VA, 1", RegisterStyle::Hex),
+            Some(vec![0x6A, 0x01])
+        );
+        assert_eq!(in_style("LD V10, 1", RegisterStyle::Hex), None);
+        assert_eq!(
+            in_style("LD V10, 1", RegisterStyle::Dec),
+            Some(vec![0x6A, 0x01])
+        );
+        assert_eq!(in_style("LD va, 1", RegisterStyle::Dec), None);
+        assert_eq!(
+            in_style("LD V10, V3", RegisterStyle::Any),
+            Some(vec![0x8A, 0x30])
+        );
+        assert_eq!(
+            in_style("LD vA, 1", RegisterStyle::Any),
+            Some(vec![0x6A, 0x01])
+        );
+    }
+
+    #[test]
+    fn writes_intel_hex_records() {
+        assert_eq!(
+            to_intel_hex(&[0x00, 0xE0], 0x200),
+            ":0202000000E01C\n:00000001FF\n"
+        );
+        // 16 bytes per record, with the load address following along
+        let bytes: Vec<u8> = (0..18).collect();
+        let records = to_intel_hex(&bytes, 0x200);
+        let records: Vec<&str> = records.lines().collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1], ":020210001011CB");
+
+        let mut assembler = Assembler::builder()
+            .source(String::from("CLS\nRET\n"))
+            .output_format(RomFormat::IntelHex)
+            .build();
+        assembler.assemble().unwrap();
+        let mut rom = Vec::new();
+        assembler.write_to(&mut rom).unwrap();
+        assert_eq!(
+            String::from_utf8(rom).unwrap(),
+            ":0402000000E000EE2C\n:00000001FF\n"
+        );
+    }
+
+    #[test]
+    fn writes_to_any_writer() {
+        let mut assembler = Assembler::from_source(String::from("CLS\nRET\n"));
+        assembler.assemble().unwrap();
+        let mut cursor = io::Cursor::new(Vec::new());
+        assembler.write_to(&mut cursor).unwrap();
+        assert_eq!(cursor.into_inner(), vec![0x00, 0xE0, 0x00, 0xEE]);
+
+        let mut assembler = Assembler::builder()
+            .source(String::from("CLS\n"))
+            .output_format(RomFormat::Hex)
+            .build();
+        assembler.assemble().unwrap();
+        let mut text = Vec::new();
+        assembler.write_to(&mut text).unwrap();
+        assert_eq!(text, b"00e0\n");
+    }
+
+    #[test]
+    fn assembles_schip_mnemonics() {
+        let in_mode = |source: &str, mode| {
+            Assembler::builder()
+                .source(source.to_string())
+                .mode(mode)
+                .assemble()
+                .map_err(|errors| errors.to_string())
+        };
+        let schip = ChipMode::SuperChip;
+        assert_eq!(in_mode("SCD 4", schip).unwrap(), vec![0x00, 0xC4]);
+        assert_eq!(
+            in_mode("scr\nSCL\nEXIT\nLOW\nHIGH", schip).unwrap(),
+            vec![0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFD, 0x00, 0xFE, 0x00, 0xFF]
+        );
+        assert_eq!(in_mode("DRW V1, V2, 0", schip).unwrap(), vec![0xD1, 0x20]);
+        assert!(in_mode("SCD 16", schip).is_err());
+
+        assert!(in_mode("HIGH", ChipMode::Chip8)
+            .unwrap_err()
+            .contains("HIGH requires SUPER-CHIP mode"));
+        assert!(in_mode("SCD 1", ChipMode::Chip8).is_err());
+        // XO-CHIP includes SUPER-CHIP
+        assert_eq!(in_mode("SCR", ChipMode::XoChip).unwrap(), vec![0x00, 0xFB]);
+    }
+
+    #[test]
+    fn assembles_xo_chip_mnemonics() {
+        let in_mode = |source: &str, mode| {
+            Assembler::builder()
+                .source(source.to_string())
+                .mode(mode)
+                .assemble()
+                .map_err(|errors| errors.to_string())
+        };
+        let xo_chip = ChipMode::XoChip;
+        assert_eq!(in_mode("PLANE 3", xo_chip).unwrap(), vec![0xF3, 0x01]);
+        assert_eq!(
+            in_mode("LD [I], V1..V4", xo_chip).unwrap(),
+            vec![0x51, 0x42]
+        );
+        assert_eq!(
+            in_mode("ld [i], v4..v1", xo_chip).unwrap(),
+            vec![0x54, 0x12]
+        );
+        assert_eq!(
+            in_mode("LD V0..VF, [I]", xo_chip).unwrap(),
+            vec![0x50, 0xF3]
+        );
+        assert_eq!(in_mode("LD I, V2..V3", xo_chip).unwrap(), vec![0x52, 0x32]);
+        assert!(in_mode("LD V0..V3, 5", xo_chip).is_err());
+        assert!(in_mode("LD [I], V0..V16", xo_chip).is_err());
+        assert_eq!(
+            in_mode("AUDIO\nPITCH V5\nSCU 7", xo_chip).unwrap(),
+            vec![0xF0, 0x02, 0xF5, 0x3A, 0x00, 0xD7]
+        );
+
+        assert!(in_mode("PLANE 1", ChipMode::SuperChip)
+            .unwrap_err()
+            .contains("PLANE requires XO-CHIP mode"));
+        assert!(in_mode("LD [I], V1..V4", ChipMode::Chip8).is_err());
+        // The classic forms are untouched
+        assert_eq!(
+            in_mode("LD [I], V4\nLD V3, [I]", ChipMode::Chip8).unwrap(),
+            vec![0xF4, 0x55, 0xF3, 0x65]
+        );
     }
 }
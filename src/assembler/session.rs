@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+
+use crate::assembler::config::AssemblerConfig;
+use crate::assembler::{Assembler, ParseError, ParseErrors};
+
+// Incremental assembly for a REPL: lines are fed one at a time and the whole program is
+// re-assembled on each line, so references to labels defined later resolve once they are fed
+pub struct AssemblerSession {
+    assembler: Assembler,
+}
+
+impl AssemblerSession {
+    pub fn new() -> Self {
+        Self::with_config(AssemblerConfig::default())
+    }
+
+    pub fn with_config(config: AssemblerConfig) -> Self {
+        Self {
+            assembler: Assembler::with_source(String::new(), String::new(), config),
+        }
+    }
+
+    // Append a source line, updating the labels and the current address. A line with errors
+    // is dropped and its errors returned, unless it only refers to labels not defined yet.
+    pub fn feed(&mut self, line: &str) -> Result<(), ParseErrors> {
+        let kept_len = self.assembler.source_code.len();
+        let first_line = self.assembler.source_code.lines().count() + 1;
+        self.assembler.source_code.push_str(line);
+        self.assembler.source_code.push('\n');
+        self.assembler.parse_lines();
+
+        let errors: Vec<ParseError> = self
+            .assembler
+            .errors
+            .iter()
+            .filter(|error| error.line >= first_line)
+            .cloned()
+            .collect();
+        if errors.iter().all(ParseError::is_undefined_name) {
+            return Ok(());
+        }
+        self.assembler.source_code.truncate(kept_len);
+        self.assembler.parse_lines();
+        Err(ParseErrors(errors))
+    }
+
+    // ROM of every line fed so far, or the errors, such as labels still undefined
+    pub fn emit(&mut self) -> Result<Vec<u8>, ParseErrors> {
+        self.assembler.assemble()
+    }
+
+    // Address the next fed instruction is assembled at
+    pub fn address(&self) -> u16 {
+        self.assembler.address as u16
+    }
+
+    // Labels defined so far, with their addresses
+    pub fn labels(&self) -> &HashMap<String, u16> {
+        &self.assembler.labels
+    }
+
+    pub fn source(&self) -> &str {
+        &self.assembler.source_code
+    }
+}
+
+impl Default for AssemblerSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_labels_fed_later() {
+        let mut session = AssemblerSession::new();
+        assert!(session.feed("JP done").is_ok());
+        assert!(session.emit().is_err());
+        assert_eq!(session.address(), 0x202);
+        assert!(session.feed("CLS").is_ok());
+        assert!(session.feed("done:").is_ok());
+        assert_eq!(session.labels().get("done"), Some(&0x204));
+        assert!(session.feed("RET").is_ok());
+        assert_eq!(
+            session.emit().unwrap(),
+            vec![0x12, 0x04, 0x00, 0xE0, 0x00, 0xEE]
+        );
+        assert_eq!(session.address(), 0x206);
+    }
+
+    #[test]
+    fn drops_lines_that_fail() {
+        let mut session = AssemblerSession::new();
+        session.feed("CLS").unwrap();
+        let errors = session.feed("LD V0, 0x100").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 2);
+        assert!(session.feed("FOO V0").is_err());
+        assert_eq!(session.source(), "CLS\n");
+        assert_eq!(session.address(), 0x202);
+        session.feed("DB <table, >table").unwrap();
+        session.feed("table:").unwrap();
+        assert_eq!(session.emit().unwrap(), vec![0x00, 0xE0, 0x04, 0x02]);
+    }
+}
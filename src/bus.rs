@@ -1,4 +1,5 @@
 use crate::display::Display;
+#[cfg(feature = "std")]
 use minifb::Key;
 
 pub struct Bus {
@@ -14,6 +15,7 @@ impl Bus {
         }
     }
 
+    #[cfg(feature = "std")]
     pub fn set_key_pressed(&mut self, key: Option<Key>) {
         self.key_pressed = self.decode_key(key);
     }
@@ -26,6 +28,7 @@ impl Bus {
         }
     }
 
+    #[cfg(feature = "std")]
     fn decode_key(&self, key: Option<Key>) -> Option<u8> {
         match key {
             Some(Key::Key1) => Some(0x1),
@@ -51,3 +54,9 @@ impl Bus {
         }
     }
 }
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -12,6 +12,12 @@ use crate::cpu::Cpu;
 const SCREEN_WIDTH: usize = 640;
 const SCREEN_HEIGHT: usize = 320;
 
+// Colors for each palette index, i.e. each combination of XO-CHIP planes
+const PALETTE: [u32; 16] = [
+    0x000000, 0x00ff00, 0xff0000, 0xffff00, 0x0000ff, 0x00ffff, 0xff00ff, 0xffffff, 0x404040,
+    0x008000, 0x800000, 0x808000, 0x000080, 0x008080, 0x800080, 0xc0c0c0,
+];
+
 pub struct Chip8 {
     bus: Bus,
     cpu: Cpu,
@@ -103,12 +109,14 @@ impl Chip8 {
     fn update_display(&self, in_buffer: &[u32]) -> Vec<u32> {
         let mut buffer = in_buffer.to_owned();
         let chip8_buffer = self.get_frame_buffer();
+        // Window pixels per display pixel, 10 in lo-res and 5 in hi-res
+        let scale = SCREEN_WIDTH / self.bus.display.width();
         for y in 0..SCREEN_HEIGHT {
             for x in 0..SCREEN_WIDTH {
-                let index = self.get_frame_index(x / 10, y / 10);
+                let index = self.get_frame_index(x / scale, y / scale);
                 let pixel = chip8_buffer[index];
 
-                let color = if pixel == 1 { 0x00ff00 } else { 0x0 };
+                let color = PALETTE[pixel as usize & 0x0F];
                 let offset = y * SCREEN_WIDTH + x;
                 buffer[offset] = color;
             }
@@ -117,7 +125,10 @@ impl Chip8 {
     }
 
     fn execute_cycle(&mut self) {
-        self.cpu.execute_cycle(&mut self.bus);
+        // A halted program keeps its last frame on screen until the window is closed
+        if !self.cpu.is_halted() {
+            self.cpu.execute_cycle(&mut self.bus);
+        }
     }
 
     fn get_frame_buffer(&self) -> &[u8] {
@@ -132,8 +143,8 @@ impl Chip8 {
         self.bus.set_key_pressed(key);
     }
 
-    fn should_redraw(&self) -> bool {
-        self.cpu.should_redraw()
+    fn should_redraw(&mut self) -> bool {
+        self.cpu.frame_buffer_changed()
     }
 
     fn should_beep(&self) -> bool {
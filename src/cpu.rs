@@ -1,109 +1,499 @@
 use crate::bus::Bus;
-use crate::ram::Ram;
-use rand::Rng;
-use std::fmt;
+use crate::opcodes::{required_mode, ChipMode};
+use crate::ram::{Ram, RAM_SIZE};
+use alloc::{collections::BTreeSet, string::String, vec::Vec};
+use core::fmt;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+type Handler = fn(&mut Cpu, &mut Bus, Operands);
+
+// Top-level dispatch, indexed by the first nibble of the opcode
+const DISPATCH_TABLE: [Handler; 16] = [
+    Cpu::dispatch_0nnn,
+    |cpu, _, ops| cpu.op_1nnn(ops.nnn), // 1NNN - JP addr: Jump to location nnn.
+    |cpu, _, ops| cpu.op_2nnn(ops.nnn), // 2NNN - CALL addr: Call subroutine at nnn.
+    |cpu, _, ops| cpu.op_3xkk(ops.x, ops.kk), // 3XKK - SE Vx, byte: Skip next instruction if Vx = kk.
+    |cpu, _, ops| cpu.op_4xkk(ops.x, ops.kk), // 4XKK - SNE Vx, byte: Skip next instruction if Vx != kk.
+    Cpu::dispatch_5xyn,
+    |cpu, _, ops| cpu.op_6xkk(ops.x, ops.kk), // 6XKK - LD Vx, byte: Set Vx = kk.
+    |cpu, _, ops| cpu.op_7xkk(ops.x, ops.kk), // 7XKK - ADD Vx, byte: Set Vx = Vx + kk.
+    |cpu, bus, ops| ALU_TABLE[ops.n as usize](cpu, bus, ops),
+    |cpu, _, ops| cpu.op_9xy0(ops.x, ops.y), // 9XY0 - SNE Vx, Vy: Skip next instruction if Vx != Vy.
+    |cpu, _, ops| cpu.op_annn(ops.nnn),      // ANNN - LD I, addr: Set I to NNN
+    |cpu, _, ops| cpu.op_bnnn(ops.nnn),      // BNNN - JP V0, addr: Jump to location nnn + V0.
+    |cpu, _, ops| cpu.op_cxkk(ops.x, ops.kk), // CXKK - RND Vx, byte: Set Vx = random byte AND kk.
+    |cpu, bus, ops| cpu.op_dxyn(bus, ops.x, ops.y, ops.n), // DXYN - DRW, Vx, Vy, nibble: Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
+    Cpu::dispatch_exkk,
+    |cpu, bus, ops| MISC_TABLE[ops.kk as usize](cpu, bus, ops),
+];
+
+// 0x8 opcodes, indexed by the last nibble
+const ALU_TABLE: [Handler; 16] = [
+    |cpu, _, ops| cpu.op_8xy0(ops.x, ops.y), //  8XY0 - LD Vx, Vy: Set Vx = Vy.
+    |cpu, _, ops| cpu.op_8xy1(ops.x, ops.y), //  8XY1 - OR Vx, Vy: Set Vx = Vx OR Vy.
+    |cpu, _, ops| cpu.op_8xy2(ops.x, ops.y), //  8XY2 - AND Vx, Vy: Set Vx = Vx AND Vy.
+    |cpu, _, ops| cpu.op_8xy3(ops.x, ops.y), //  8XY3 - XOR Vx, Vy: Set Vx = Vx XOR Vy.
+    |cpu, _, ops| cpu.op_8xy4(ops.x, ops.y), //  8XY4 - ADD Vx, Vy: Set Vx = Vx + Vy, set VF = carry.
+    |cpu, _, ops| cpu.op_8xy5(ops.x, ops.y), //  8XY5 - SUB Vx, Vy: Set Vx = Vx - Vy, set VF = NOT borrow.
+    |cpu, _, ops| cpu.op_8xy6(ops.x),        //  8XY6 - SHR Vx: Set Vx = Vx SHR 1.
+    |cpu, _, ops| cpu.op_8xy7(ops.x, ops.y), //  8XY7 - SUBN Vx, Vy: Set Vx = Vy - Vx, set VF = NOT borrow.
+    Cpu::op_unknown,
+    Cpu::op_unknown,
+    Cpu::op_unknown,
+    Cpu::op_unknown,
+    Cpu::op_unknown,
+    Cpu::op_unknown,
+    |cpu, _, ops| cpu.op_8xye(ops.x), //  8XYE - SHL Vx: Set Vx = Vx SHL 1.
+    Cpu::op_unknown,
+];
+
+// XO-CHIP pitch register value for the 4000 Hz playback rate of the original CHIP-8 beep
+const DEFAULT_PITCH: u8 = 64;
+
+// 0xF opcodes, indexed by the low byte
+const MISC_TABLE: [Handler; 256] = {
+    let mut table: [Handler; 256] = [Cpu::op_unknown; 256];
+    table[0x01] = |cpu, bus, ops| cpu.op_fn01(ops.x, bus); //  FN01 - PLANE n: Select the XO-CHIP drawing planes n (bitmask).
+    table[0x02] = |cpu, bus, ops| match ops.x {
+        0 => cpu.op_f002(), //  F002 - AUDIO: Load the XO-CHIP audio pattern from memory at I.
+        _ => cpu.op_unknown(bus, ops),
+    };
+    table[0x07] = |cpu, _, ops| cpu.op_fx07(ops.x); //  FX07 - LD Vx, DT: Set Vx = delay timer value.
+    table[0x0A] = |cpu, bus, ops| cpu.op_fx0a(ops.x, bus); //  FX0A - LD Vx, K: Wait for a key press, store the value of the key in Vx.
+    table[0x15] = |cpu, _, ops| cpu.op_fx15(ops.x); //  FX15 - LD DT, Vx: Set delay timer = Vx.
+    table[0x18] = |cpu, _, ops| cpu.op_fx18(ops.x); //  FX18 - LD ST, Vx: Set sound timer = Vx.
+    table[0x1E] = |cpu, _, ops| cpu.op_fx1e(ops.x); //  FX1E - ADD I, Vx: Set I = I + Vx.
+    table[0x29] = |cpu, _, ops| cpu.op_fx29(ops.x); //  FX29 - LD F, Vx: Set I = location of sprite for digit Vx.
+    table[0x33] = |cpu, _, ops| cpu.op_fx33(ops.x); //  FX33 - LD B, Vx: Store BCD representation of Vx in memory locations I, I+1, and I+2.
+    table[0x3A] = |cpu, _, ops| cpu.op_fx3a(ops.x); //  FX3A - PITCH Vx: Set the XO-CHIP audio pitch = Vx.
+    table[0x55] = |cpu, _, ops| cpu.op_fx55(ops.x); //  FX55 - LD [I], Vx: Store registers V0 through Vx in memory starting at location I.
+    table[0x65] = |cpu, _, ops| cpu.op_fx65(ops.x); //  FX65 - Ld Vx, [I]: Read registers V0 through Vx from memory starting at location I.
+    table[0x75] = |cpu, _, ops| cpu.op_fx75(ops.x); //  FX75 - LD R, Vx: Store V0 through Vx in the flag registers (SCHIP).
+    table[0x85] = |cpu, _, ops| cpu.op_fx85(ops.x); //  FX85 - LD Vx, R: Read V0 through Vx from the flag registers (SCHIP).
+    table
+};
+
+// Approximate COSMAC VIP execution time of an opcode, in machine cycles
+pub fn cycle_cost(opcode: u16) -> usize {
+    let x = ((opcode & 0x0F00) >> 8) as usize;
+    let n = (opcode & 0x000F) as usize;
+    match opcode & 0xF000 {
+        0x0000 if opcode == 0x00E0 => 24,
+        0x0000 => 10,
+        0x1000 => 12,
+        0x2000 => 26,
+        0x3000 | 0x4000 => 10,
+        0x5000 | 0x9000 => 14,
+        0x6000 => 6,
+        0x7000 => 10,
+        0x8000 => 44,
+        0xA000 => 12,
+        0xB000 => 22,
+        0xC000 => 36,
+        // Sprite rows are shifted and XORed one by one
+        0xD000 => 22 + 34 * n,
+        0xE000 => 14,
+        _ => match opcode & 0x00FF {
+            0x1E => 16,
+            0x29 => 20,
+            0x33 => 84,
+            0x55 | 0x65 => 14 + 14 * (x + 1),
+            _ => 10,
+        },
+    }
+}
+
+// Headless run of a ROM for a fixed number of instructions, returning the resulting frame as
+// ASCII; handy for golden-file checks of assembler output against the interpreter and display
+pub fn render_ascii(rom_buffer: &[u8], cycles: usize) -> String {
+    let mut cpu = Cpu::new(rom_buffer);
+    let mut bus = Bus::new();
+    cpu.run_frame(&mut bus, cycles);
+    bus.display.to_ascii()
+}
+
+// Entropy-seeded with std; without an OS to ask, a fixed seed until `Cpu::seed_rng` is called
+#[cfg(feature = "std")]
+fn new_rng() -> SmallRng {
+    SmallRng::from_entropy()
+}
+
+#[cfg(not(feature = "std"))]
+fn new_rng() -> SmallRng {
+    SmallRng::seed_from_u64(0x8C4A_F1D2_0E37_B965)
+}
+
+// Opcode broken up into the fields used by the handlers
+#[derive(Clone, Copy)]
+struct Operands {
+    opcode: u16,
+    x: usize,
+    y: usize,
+    n: u8,
+    kk: u8,
+    nnn: u16,
+}
+
+impl Operands {
+    fn new(opcode: u16) -> Self {
+        Self {
+            opcode,
+            x: ((opcode & 0x0F00) >> 8) as usize,
+            y: ((opcode & 0x00F0) >> 4) as usize,
+            n: (opcode & 0x000F) as u8,
+            kk: (opcode & 0x00FF) as u8,
+            nnn: opcode & 0x0FFF,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RunResult {
+    Completed,
+    // Execution stopped before the instruction at this address
+    BreakpointHit(u16),
+    // Execution left the loaded program and stopped before the instruction at this address
+    ExecutedPastProgram(u16),
+    // The program exited (00FD)
+    Halted,
+    // An unknown opcode was hit with `IllegalPolicy::Error`
+    IllegalOpcode(u16),
+}
+
+// Outcome of `run_to_halt`
+#[derive(Debug, PartialEq)]
+pub struct RunOutcome {
+    // The program stopped by itself (EXIT, a jump to itself or an unknown opcode) within the cap
+    pub halted: bool,
+    // Instructions executed, including the one that halted
+    pub cycles: usize,
+}
+
+// Outcome of a single `step`, telling frontends what to react to
+#[derive(Debug, PartialEq)]
+pub enum StepResult {
+    Continue,
+    // The display was modified
+    Redraw,
+    // The program exited (00FD), further steps do nothing
+    Halted,
+    // Fx0A is blocking until a key is pressed
+    WaitKey,
+    // Stopped before the instruction at this address
+    Breakpoint(u16),
+    // An unknown opcode was hit with `IllegalPolicy::Error`, the pc still points at it
+    IllegalOpcode(u16),
+}
+
+// What to do when an unknown opcode, or a RET with an empty stack, is executed
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum IllegalPolicy {
+    // Log the opcode and stop, as if the program exited
+    #[default]
+    Halt,
+    // Log the opcode and treat it as a no-op
+    Skip,
+    // Stop and report the opcode through `step`/`run_frame`
+    Error,
+}
+
+// Behaviours that differ between CHIP-8 interpreters, defaults match modern interpreters
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Quirks {
+    // 8xy1/8xy2/8xy3 reset VF to 0, as on the original COSMAC VIP
+    pub vf_reset: bool,
+}
+
 pub struct Cpu {
-    pub ram: Ram,
-    pub pc: usize,
-    pub v: [u8; 16],
-    pub i: usize,
+    ram: Ram,
+    pub quirks: Quirks,
+    // Interpreter emulated, opcodes of later ones are illegal (see `on_illegal`)
+    pub mode: ChipMode,
+    pub on_illegal: IllegalPolicy,
+    // `run_frame` budgets COSMAC VIP machine cycles (see `cycle_cost`) instead of instructions
+    pub cycle_accurate: bool,
+    // Stop `run_frame` when the pc leaves the loaded program, which usually means it ran off the end
+    pub warn_past_program: bool,
+    program_end: usize,
+    pc: usize,
+    v: [u8; 16],
+    // SCHIP flag registers (the HP48 RPL user flags), kept by frontends to persist them
+    flags: [u8; 8],
+    i: usize,
     stack: Vec<usize>,
     delay_timer: u8,
     sound_timer: u8,
+    // XO-CHIP audio: 128 one-bit samples played while the sound timer runs, None until loaded
+    audio_pattern: Option<[u8; 16]>,
+    pitch: u8,
     draw_flag: bool,
-    // paused: bool,
+    // Set whenever the display is modified, cleared by `frame_buffer_changed`
+    display_dirty: bool,
+    breakpoints: BTreeSet<u16>,
+    // Breakpoint execution last stopped at, so resuming doesn't immediately stop again
+    paused_at: Option<u16>,
+    halted: bool,
+    waiting_for_key: bool,
+    // Unknown opcode that stopped execution under `IllegalPolicy::Error`
+    illegal_opcode: Option<u16>,
+    rng: SmallRng,
 }
 
 impl Cpu {
     pub fn new(rom_buffer: &[u8]) -> Self {
         Self {
             ram: Ram::new(rom_buffer),
+            quirks: Quirks::default(),
+            mode: ChipMode::XoChip,
+            on_illegal: IllegalPolicy::default(),
+            cycle_accurate: false,
+            warn_past_program: false,
+            program_end: 0x200 + rom_buffer.len(),
             pc: 0x200,
             v: [0x00; 16],
+            flags: [0x00; 8],
             i: 0,
             stack: Vec::new(),
             delay_timer: 0,
             sound_timer: 0,
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
             draw_flag: false,
+            display_dirty: false,
+            breakpoints: BTreeSet::new(),
+            paused_at: None,
+            halted: false,
+            waiting_for_key: false,
+            illegal_opcode: None,
+            rng: new_rng(),
+        }
+    }
+
+    // Reseed the RND generator, e.g. from a hardware source or for reproducible runs
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = SmallRng::seed_from_u64(seed);
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) {
+        self.breakpoints.remove(&address);
+    }
+
+    // Execute up to `cycles` instructions (or machine cycles when cycle accurate), stopping before
+    // any instruction sitting on a breakpoint
+    pub fn run_frame(&mut self, bus: &mut Bus, cycles: usize) -> RunResult {
+        let mut budget = cycles;
+        while budget > 0 {
+            let pc = self.pc as u16;
+            if self.warn_past_program && !self.in_program(self.pc) && self.paused_at != Some(pc) {
+                self.paused_at = Some(pc);
+                return RunResult::ExecutedPastProgram(pc);
+            }
+            let cost = if self.cycle_accurate {
+                cycle_cost(self.fetch_op())
+            } else {
+                1
+            };
+            match self.step(bus) {
+                StepResult::Breakpoint(address) => return RunResult::BreakpointHit(address),
+                StepResult::Halted => return RunResult::Halted,
+                StepResult::IllegalOpcode(opcode) => return RunResult::IllegalOpcode(opcode),
+                _ => {}
+            }
+            budget = budget.saturating_sub(cost);
+        }
+        RunResult::Completed
+    }
+
+    // Run until the program halts, through EXIT or the `JP $` idiom of classic CHIP-8, executing
+    // at most `max_cycles` instructions. Breakpoints are ignored.
+    pub fn run_to_halt(&mut self, bus: &mut Bus, max_cycles: usize) -> RunOutcome {
+        let mut cycles = 0;
+        while cycles < max_cycles && !self.halted && self.illegal_opcode.is_none() {
+            let pc = self.pc;
+            let opcode = self.fetch_op();
+            if let StepResult::Breakpoint(_) = self.step(bus) {
+                continue;
+            }
+            cycles += 1;
+            if opcode == 0x1000 | pc as u16 {
+                return RunOutcome {
+                    halted: true,
+                    cycles,
+                };
+            }
         }
+        RunOutcome {
+            halted: self.halted,
+            cycles,
+        }
+    }
+
+    // Execute a single instruction, unless halted or sitting on a breakpoint
+    pub fn step(&mut self, bus: &mut Bus) -> StepResult {
+        if let Some(opcode) = self.illegal_opcode {
+            return StepResult::IllegalOpcode(opcode);
+        }
+        if self.halted {
+            return StepResult::Halted;
+        }
+        let pc = self.pc as u16;
+        if self.breakpoints.contains(&pc) && self.paused_at != Some(pc) {
+            self.paused_at = Some(pc);
+            return StepResult::Breakpoint(pc);
+        }
+        self.paused_at = None;
+        self.execute_cycle(bus);
+
+        if let Some(opcode) = self.illegal_opcode {
+            StepResult::IllegalOpcode(opcode)
+        } else if self.halted {
+            StepResult::Halted
+        } else if self.waiting_for_key {
+            StepResult::WaitKey
+        } else if self.draw_flag {
+            StepResult::Redraw
+        } else {
+            StepResult::Continue
+        }
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // Read-only views of the machine state, e.g. for a debugger
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    pub fn index(&self) -> usize {
+        self.i
+    }
+
+    pub fn program_counter(&self) -> usize {
+        self.pc
+    }
+
+    // Stack pointer, i.e. the number of return addresses on the stack
+    pub fn sp(&self) -> usize {
+        self.stack.len()
+    }
+
+    pub fn stack(&self) -> &[usize] {
+        &self.stack
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn ram(&self) -> &Ram {
+        &self.ram
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.ram.memory
+    }
+
+    pub fn flags(&self) -> &[u8; 8] {
+        &self.flags
+    }
+
+    // Restore flag registers persisted from an earlier run
+    pub fn set_flags(&mut self, flags: [u8; 8]) {
+        self.flags = flags;
     }
 
     pub fn execute_cycle(&mut self, bus: &mut Bus) {
         self.draw_flag = false;
         let opcode = self.fetch_op();
-        self.decode_op(opcode, bus);
+        self.execute(opcode, bus);
+        // The pc wraps around the end of memory rather than running off it
+        self.pc %= RAM_SIZE;
     }
 
     fn fetch_op(&mut self) -> u16 {
         // Load from self.pc (2 bytes), so fetch two successive bytes
-        let hi_byte = self.ram.read_byte(self.pc) as u16;
-        let lo_byte = self.ram.read_byte(self.pc + 1) as u16;
+        let hi_byte = self.ram.read_byte(self.pc % RAM_SIZE) as u16;
+        let lo_byte = self.ram.read_byte((self.pc + 1) % RAM_SIZE) as u16;
         hi_byte << 8 | lo_byte
     }
 
-    fn decode_op(&mut self, opcode: u16, bus: &mut Bus) {
-        // Break up 2byte opcode into nibbles and bytes
-        let nibbles = (
-            ((opcode & 0xF000) >> 12) as u8,
-            ((opcode & 0x0F00) >> 8) as u8,
-            ((opcode & 0x00F0) >> 4) as u8,
-            (opcode & 0x000F) as u8,
-        );
-        let x = nibbles.1 as usize;
-        let y = nibbles.2 as usize;
-        let n = nibbles.3 as u8;
-        let kk = (opcode & 0x00FF) as u8;
-        let nnn = opcode & 0x0FFF;
-        match nibbles {
-            //
-            (0x00, _, _, _) => match kk {
-                0xE0 => self.op_00e0(bus), // 00E0 - CLS: Clear display
-                0xEE => self.op_00ee(),    // 00EE - RET : Return from subroutine
-                _ => println!("Unrecognized opcode {:?}", opcode),
-            },
-            (0x01, _, _, _) => self.op_1nnn(nnn), // 1NNN - JP addr: Jump to location nnn.
-            (0x02, _, _, _) => self.op_2nnn(nnn), // 2NNN - CALL addr: Call subroutine at nnn.
-            (0x03, _, _, _) => self.op_3xkk(x, kk), // 3XKK - SE Vx, byte: Skip next instruction if Vx = kk.
-            (0x04, _, _, _) => self.op_4xkk(x, kk), // 4XKK - SNE Vx, byte: Skip next instruction if Vx != kk.
-            (0x05, _, _, _) => self.op_5xy0(x, y), // 5XY0 - SE Vx, Vy: Skip next instruction if Vx = Vy.
-            (0x06, _, _, _) => self.op_6xkk(x, kk), // 6XKK - LD Vx, byte: Set Vx = kk.
-            (0x07, _, _, _) => self.op_7xkk(x, kk), // 7XKK - ADD Vx, byte: Set Vx = Vx + kk.
-            (0x08, _, _, _) => match n {
-                0x00 => self.op_8xy0(x, y), //  8XY0 - LD Vx, Vy: Set Vx = Vy.
-                0x01 => self.op_8xy1(x, y), //  8XY1 - OR Vx, Vy: Set Vx = Vx OR Vy.
-                0x02 => self.op_8xy2(x, y), //  8XY2 - AND Vx, Vy: Set Vx = Vx AND Vy.
-                0x03 => self.op_8xy3(x, y), //  8XY3 - XOR Vx, Vy: Set Vx = Vx XOR Vy.
-                0x04 => self.op_8xy4(x, y), //  8XY4 - ADD Vx, Vy: Set Vx = Vx + Vy, set VF = carry.
-                0x05 => self.op_8xy5(x, y), //  8XY5 - SUB Vx, Vy: Set Vx = Vx - Vy, set VF = NOT borrow.
-                0x06 => self.op_8xy6(x),    //  8XY6 - SHR Vx: Set Vx = Vx SHR 1.
-                0x07 => self.op_8xy7(x, y), //  8XY7 - SUBN Vx, Vy: Set Vx = Vy - Vx, set VF = NOT borrow.
-                0x0E => self.op_8xye(x),    //  8XYE - SHL Vx: Set Vx = Vx SHL 1.
-                _ => println!("Unrecognized opcode {:?}", opcode),
-            },
-            (0x09, _, _, _) => self.op_9xy0(x, y), // 9XY0 - SNE Vx, Vy: Skip next instruction if Vx != Vy.
-            (0x0A, _, _, _) => self.op_annn(nnn),  // ANNN - LD I, addr: Set I to NNN
-            (0x0B, _, _, _) => self.op_bnnn(nnn),  // BNNN - JP V0, addr: Jump to location nnn + V0.
-            (0x0C, _, _, _) => self.op_cxkk(x, kk), // CXKK - RND Vx, byte: Set Vx = random byte AND kk.
-            (0x0D, _, _, _) => self.op_dxyn(bus, x, y, n), // DXYN - DRW, Vx, Vy, nibble: Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
-            (0x0E, _, _, _) => match kk {
-                0x9E => self.op_ex9e(x, bus), //  Ex9E - SKP Vx:  Skip next instruction if key with the value of Vx is pressed.
-                0xA1 => self.op_exa1(x, bus), //  EXA1 - SKNP Vx: Skip next instruction if key with the value of Vx is not pressed.
-
-                _ => println!("Unrecognized opcode {:?}", opcode),
-            },
-            (0x0F, _, _, _) => match kk {
-                0x07 => self.op_fx07(x), //  FX07 - LD Vx, DT: Set Vx = delay timer value. The value of DT is placed into Vx.
-                0x0A => self.op_fx0a(x, bus), //  FX0A - LD Vx, K: Wait for a key press, store the value of the key in Vx.
-                0x15 => self.op_fx15(x),      //  FX15 - LD DT, Vx: Set delay timer = Vx.
-                0x18 => self.op_fx18(x),      //  FX18 - LD ST, Vx: Set sound timer = Vx.
-                0x1E => self.op_fx1e(x),      //  FX1E - ADD I, Vx: Set I = I + Vx.
-                0x29 => self.op_fx29(x), //  FX29 - LD F, Vx: Set I = location of sprite for digit Vx.
-                0x33 => self.op_fx33(x), //  FX33 - LD B, Vx: Store BCD representation of Vx in memory locations I, I+1, and I+2.
-                0x55 => self.op_fx55(x), //  FX55 - LD [I], Vx: Store registers V0 through Vx in memory starting at location I.
-                0x65 => self.op_fx65(x), //  FX65 - Ld Vx, [I]: Read registers V0 through Vx from memory starting at location I.
-                _ => println!("Unrecognized opcode {:?}", opcode),
-            },
-            _ => println!("Unrecognized opcode {:?}", opcode),
+    fn in_program(&self, address: usize) -> bool {
+        (0x200..self.program_end).contains(&address)
+    }
+
+    fn execute(&mut self, opcode: u16, bus: &mut Bus) {
+        let ops = Operands::new(opcode);
+        // Every opcode is known in XO-CHIP mode, so the lookup is only needed for the others
+        if self.mode < ChipMode::XoChip
+            && matches!(required_mode(opcode), Some(mode) if mode > self.mode)
+        {
+            return self.op_unknown(bus, ops);
+        }
+        DISPATCH_TABLE[(opcode >> 12) as usize](self, bus, ops);
+    }
+
+    // 0x0 opcodes only differ by their low byte, so a match is cheaper than another table
+    fn dispatch_0nnn(&mut self, bus: &mut Bus, ops: Operands) {
+        match ops.kk {
+            0xE0 => self.op_00e0(bus),        // 00E0 - CLS: Clear display
+            0xEE => self.op_00ee(bus, ops),   // 00EE - RET : Return from subroutine
+            0xFD => self.op_00fd(),           // 00FD - EXIT: Exit the interpreter (SCHIP)
+            0xFE => self.op_00fe(bus, false), // 00FE - LOW: Switch to 64x32 (SCHIP)
+            0xFF => self.op_00fe(bus, true),  // 00FF - HIGH: Switch to 128x64 (SCHIP)
+            _ => self.op_unknown(bus, ops),
+        }
+    }
+
+    fn dispatch_5xyn(&mut self, bus: &mut Bus, ops: Operands) {
+        match ops.n {
+            0x0 => self.op_5xy0(ops.x, ops.y), // 5XY0 - SE Vx, Vy: Skip next instruction if Vx = Vy.
+            0x2 => self.op_5xy2(ops.x, ops.y), // 5XY2 - Store Vx through Vy in memory starting at location I (XO-CHIP).
+            0x3 => self.op_5xy3(ops.x, ops.y), // 5XY3 - Read Vx through Vy from memory starting at location I (XO-CHIP).
+            _ => self.op_unknown(bus, ops),
+        }
+    }
+
+    fn dispatch_exkk(&mut self, bus: &mut Bus, ops: Operands) {
+        match ops.kk {
+            0x9E => self.op_ex9e(ops.x, bus), //  Ex9E - SKP Vx:  Skip next instruction if key with the value of Vx is pressed.
+            0xA1 => self.op_exa1(ops.x, bus), //  EXA1 - SKNP Vx: Skip next instruction if key with the value of Vx is not pressed.
+            _ => self.op_unknown(bus, ops),
+        }
+    }
+
+    fn op_unknown(&mut self, _bus: &mut Bus, ops: Operands) {
+        match self.on_illegal {
+            IllegalPolicy::Halt => {
+                #[cfg(feature = "std")]
+                println!(
+                    "Unrecognized opcode {:04x} at {:03x}, halting",
+                    ops.opcode, self.pc
+                );
+                self.halted = true;
+            }
+            IllegalPolicy::Skip => {
+                #[cfg(feature = "std")]
+                println!(
+                    "Unrecognized opcode {:04x} at {:03x}, skipped",
+                    ops.opcode, self.pc
+                );
+                self.pc += 2;
+            }
+            IllegalPolicy::Error => {
+                self.illegal_opcode = Some(ops.opcode);
+                self.halted = true;
+            }
         }
     }
 
@@ -120,19 +510,58 @@ impl Cpu {
         self.sound_timer > 0
     }
 
+    // While beeping, the XO-CHIP pattern and pitch to play instead of a plain tone; None for ROMs
+    // that never loaded a pattern. The playback rate is 4000 * 2^((pitch - 64) / 48) bits/sec.
+    pub fn beep_pattern(&self) -> Option<(&[u8; 16], u8)> {
+        match &self.audio_pattern {
+            Some(pattern) if self.should_beep() => Some((pattern, self.pitch)),
+            _ => None,
+        }
+    }
+
+    pub fn audio_pattern(&self) -> Option<&[u8; 16]> {
+        self.audio_pattern.as_ref()
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
     pub fn should_redraw(&self) -> bool {
         self.draw_flag
     }
 
+    // Whether the display changed since the last call, so frontends can skip unchanged frames
+    pub fn frame_buffer_changed(&mut self) -> bool {
+        core::mem::replace(&mut self.display_dirty, false)
+    }
+
     fn op_00e0(&mut self, bus: &mut Bus) {
         bus.display.clear();
+        self.draw_flag = true;
+        self.display_dirty = true;
         self.pc += 2;
     }
 
-    // Return from subroutine
-    fn op_00ee(&mut self) {
-        let address = self.stack.pop().unwrap();
-        self.pc = address;
+    // Return from subroutine; with nothing to return to, RET is an illegal opcode
+    fn op_00ee(&mut self, bus: &mut Bus, ops: Operands) {
+        match self.stack.pop() {
+            Some(address) => self.pc = address,
+            None => self.op_unknown(bus, ops),
+        }
+    }
+
+    // Exit the interpreter, the pc stays on the EXIT instruction
+    fn op_00fd(&mut self) {
+        self.halted = true;
+    }
+
+    // Switch between lo-res and hi-res, DRW then wraps at the new dimensions
+    fn op_00fe(&mut self, bus: &mut Bus, hires: bool) {
+        bus.display.set_hires(hires);
+        self.draw_flag = true;
+        self.display_dirty = true;
+        self.pc += 2;
     }
 
     // fn op_0nnn(&mut self, nnn: u16) {
@@ -177,6 +606,38 @@ impl Cpu {
         }
     }
 
+    // Registers Vx through Vy in order, descending when x > y, with the memory address of each
+    // relative to I; bytes past the end of memory are left out
+    fn register_range(&self, x: usize, y: usize) -> Vec<(usize, usize)> {
+        let registers: Vec<usize> = if x <= y {
+            (x..=y).collect()
+        } else {
+            (y..=x).rev().collect()
+        };
+        registers
+            .into_iter()
+            .enumerate()
+            .map(|(offset, reg)| (reg, self.i + offset))
+            .filter(|&(_, address)| address < RAM_SIZE)
+            .collect()
+    }
+
+    //  5xy2: Store registers Vx through Vy in memory starting at location I, I is unchanged.
+    fn op_5xy2(&mut self, x: usize, y: usize) {
+        for (reg, address) in self.register_range(x, y) {
+            self.ram.write_byte(address, self.v[reg]);
+        }
+        self.pc += 2;
+    }
+
+    //  5xy3: Read registers Vx through Vy from memory starting at location I, I is unchanged.
+    fn op_5xy3(&mut self, x: usize, y: usize) {
+        for (reg, address) in self.register_range(x, y) {
+            self.v[reg] = self.ram.read_byte(address);
+        }
+        self.pc += 2;
+    }
+
     // The interpreter puts the value kk into register Vx.
     fn op_6xkk(&mut self, x: usize, kk: u8) {
         self.v[x] = kk;
@@ -199,18 +660,27 @@ impl Cpu {
     // Performs a bitwise OR on the values of Vx and Vy, then stores the result in Vx. A bitwise OR compares the corrseponding bits from two values, and if either bit is 1, then the same bit in the result is also 1. Otherwise, it is 0.
     fn op_8xy1(&mut self, x: usize, y: usize) {
         self.v[x] |= self.v[y];
+        if self.quirks.vf_reset {
+            self.v[0xf] = 0;
+        }
         self.pc += 2;
     }
 
     // Performs a bitwise AND on the values of Vx and Vy, then stores the result in Vx. A bitwise AND compares the corrseponding bits from two values, and if both bits are 1, then the same bit in the result is also 1. Otherwise, it is 0.
     fn op_8xy2(&mut self, x: usize, y: usize) {
         self.v[x] &= self.v[y];
+        if self.quirks.vf_reset {
+            self.v[0xf] = 0;
+        }
         self.pc += 2;
     }
 
     // Performs a bitwise exclusive OR on the values of Vx and Vy, then stores the result in Vx. An exclusive OR compares the corrseponding bits from two values, and if the bits are not both the same, then the corresponding bit in the result is set to 1. Otherwise, it is 0.
     fn op_8xy3(&mut self, x: usize, y: usize) {
         self.v[x] ^= self.v[y];
+        if self.quirks.vf_reset {
+            self.v[0xf] = 0;
+        }
         self.pc += 2;
     }
 
@@ -275,8 +745,7 @@ impl Cpu {
     // Cxkk: Set Vx = random byte AND kk.
     // The interpreter generates a random number from 0 to 255, which is then ANDed with the value kk. The results are stored in Vx. See instruction 8xy2 for more information on AND.
     fn op_cxkk(&mut self, x: usize, kk: u8) {
-        let mut rng = rand::thread_rng();
-        self.v[x] = rng.gen::<u8>() & kk;
+        self.v[x] = self.rng.gen::<u8>() & kk;
         self.pc += 2;
     }
 
@@ -286,14 +755,19 @@ impl Cpu {
     // Sprites are XORed onto the existing screen. If this causes any pixels to be erased, VF is set to 1, otherwise it is set to 0.
     // If the sprite is positioned so part of it is outside the coordinates of the display, it wraps around to the opposite side of the screen. See instruction 8xy3 for more information on XOR, and section 2.4, Display, for more information on the Chip-8 screen and sprites.
     fn op_dxyn(&mut self, bus: &mut Bus, x: usize, y: usize, n: u8) {
+        // Coordinates are read before VF is cleared, so drawing at (VF, Vy) uses the old value
+        let (col, row) = (self.v[x] as usize, self.v[y] as usize);
         self.v[0xF] = 0;
-        let sprite = self.ram.read_bytes(self.i, self.i + n as usize);
+        // Each selected XO-CHIP plane reads its own n rows
+        let len = n as usize * bus.display.plane_count();
+        // Fx1E can leave I past the end of memory, which reads as an empty sprite
+        let start = self.i.min(RAM_SIZE);
+        let sprite = self.ram.read_bytes(start, (start + len).min(RAM_SIZE));
 
-        let collision = bus
-            .display
-            .draw(self.v[x] as usize, self.v[y] as usize, sprite);
+        let collision = bus.display.draw(col, row, sprite);
         self.v[0xF] = if collision { 1 } else { 0 };
         self.draw_flag = true;
+        self.display_dirty = true;
         self.pc += 2;
     }
 
@@ -303,7 +777,7 @@ impl Cpu {
         if !bus.is_key_pressed(key) {
             self.pc += 4;
         } else {
-            bus.set_key_pressed(None);
+            bus.key_pressed = None;
             self.pc += 2;
         }
     }
@@ -314,11 +788,27 @@ impl Cpu {
         if bus.is_key_pressed(key) {
             self.pc += 4;
         } else {
-            bus.set_key_pressed(None);
+            bus.key_pressed = None;
             self.pc += 2;
         }
     }
 
+    // XO-CHIP: select the planes affected by CLS and DRW, n is a bitmask.
+    fn op_fn01(&mut self, n: usize, bus: &mut Bus) {
+        bus.display.set_plane(n as u8);
+        self.pc += 2;
+    }
+
+    //  F002: Load the 16 byte audio pattern from memory starting at I.
+    fn op_f002(&mut self) {
+        let mut pattern = [0; 16];
+        for (idx, byte) in pattern.iter_mut().enumerate() {
+            *byte = self.ram.read_byte((self.i + idx) % RAM_SIZE);
+        }
+        self.audio_pattern = Some(pattern);
+        self.pc += 2;
+    }
+
     // The value of DT is placed into Vx.
     fn op_fx07(&mut self, x: usize) {
         self.v[x] = self.delay_timer;
@@ -330,8 +820,12 @@ impl Cpu {
     fn op_fx0a(&mut self, x: usize, bus: &Bus) {
         if let Some(key_pressed) = bus.key_pressed {
             self.v[x] = key_pressed;
+            self.waiting_for_key = false;
+            self.pc += 2;
+        } else {
+            // Stay on this instruction until a key is pressed
+            self.waiting_for_key = true;
         }
-        self.pc += 2;
     }
 
     //  Fx15: Set delay timer = Vx. DT is set equal to the value of Vx.
@@ -361,17 +855,21 @@ impl Cpu {
 
     //  Fx33: Store BCD representation of Vx in memory locations I, I+1, and I+2.
     // The interpreter takes the decimal value of Vx, and places the hundreds digit in memory at location in I, the tens digit at location I+1, and the ones digit at location I+2.
+    // Like Fx55, digits that would land past the end of memory are dropped.
     fn op_fx33(&mut self, x: usize) {
-        self.ram.write_byte(self.i, self.v[x] / 100);
-        self.ram.write_byte(self.i + 1, (self.v[x] % 100) / 10);
-        self.ram.write_byte(self.i + 2, self.v[x] % 10);
+        let digits = [self.v[x] / 100, (self.v[x] % 100) / 10, self.v[x] % 10];
+        for (offset, &digit) in digits.iter().enumerate() {
+            if self.i + offset < RAM_SIZE {
+                self.ram.write_byte(self.i + offset, digit);
+            }
+        }
         self.pc += 2;
     }
 
     //  Fx55: Store registers V0 through Vx in memory starting at location I.
     // The interpreter copies the values of registers V0 through Vx into memory, starting at the address in I.
     fn op_fx55(&mut self, x: usize) {
-        for idx in 0..x + 1 {
+        for idx in 0..self.register_count(x) {
             let val = self.v[idx];
             self.ram.write_byte(self.i + idx, val);
         }
@@ -381,12 +879,37 @@ impl Cpu {
     //  Fx65: Read registers V0 through Vx from memory starting at location I.
     // The interpreter reads values from memory starting at location I into registers V0 through Vx.
     fn op_fx65(&mut self, x: usize) {
-        for idx in 0..x + 1 {
+        for idx in 0..self.register_count(x) {
             let val = self.ram.read_byte(self.i + idx);
             self.v[idx] = val;
         }
         self.pc += 2;
     }
+
+    //  Fx3A: Set the audio pitch = Vx, 64 being the default 4000 Hz playback rate.
+    fn op_fx3a(&mut self, x: usize) {
+        self.pitch = self.v[x];
+        self.pc += 2;
+    }
+
+    //  Fx75: Store registers V0 through Vx in the flag registers, x is at most 7.
+    fn op_fx75(&mut self, x: usize) {
+        let count = x.min(7) + 1;
+        self.flags[..count].copy_from_slice(&self.v[..count]);
+        self.pc += 2;
+    }
+
+    //  Fx85: Read registers V0 through Vx from the flag registers, x is at most 7.
+    fn op_fx85(&mut self, x: usize) {
+        let count = x.min(7) + 1;
+        self.v[..count].copy_from_slice(&self.flags[..count]);
+        self.pc += 2;
+    }
+
+    // Number of registers V0 through Vx copied by Fx55/Fx65, bounded by the memory left after I
+    fn register_count(&self, x: usize) -> usize {
+        (x + 1).min(RAM_SIZE.saturating_sub(self.i))
+    }
 }
 
 impl fmt::Debug for Cpu {
@@ -398,3 +921,455 @@ impl fmt::Debug for Cpu {
         writeln!(f, "I: {:#X}", self.i)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Goes through the top-level table and both the 0x8 and 0xF sub-tables; the expected
+    // state is what the match-based `execute` produced before the tables replaced it
+    #[test]
+    fn dispatch_runs_sample_program() {
+        // LD V0, 5; LD V1, 3; ADD V0, V1; SUB V0, V1; LD VE, 0x81; SHL VE; LD I, 0x300;
+        // LD B, V0; CALL 0x214; JP 0x212; RET
+        let rom = [
+            0x60, 0x05, 0x61, 0x03, 0x80, 0x14, 0x80, 0x15, 0x6E, 0x81, 0x8E, 0x0E, 0xA3, 0x00,
+            0xF0, 0x33, 0x22, 0x14, 0x12, 0x12, 0x00, 0xEE,
+        ];
+        let mut cpu = Cpu::new(&rom);
+        let mut bus = Bus::new();
+        for _ in 0..10 {
+            cpu.execute_cycle(&mut bus);
+        }
+        assert_eq!(cpu.v[0x0], 5);
+        assert_eq!(cpu.v[0xE], 2);
+        assert_eq!(cpu.v[0xF], 1);
+        assert_eq!(cpu.ram.read_bytes(0x300, 0x303), &[0, 0, 5]);
+        assert_eq!(cpu.i, 0x300);
+        assert_eq!(cpu.pc, 0x212);
+        assert!(cpu.stack.is_empty());
+    }
+
+    #[test]
+    fn or_resets_vf_only_with_the_quirk() {
+        // LD VF, 1; LD V0, 0x0C; LD V1, 0x03; OR V0, V1
+        let rom = [0x6F, 0x01, 0x60, 0x0C, 0x61, 0x03, 0x80, 0x11];
+        for &vf_reset in &[false, true] {
+            let mut cpu = Cpu::new(&rom);
+            let mut bus = Bus::new();
+            cpu.quirks.vf_reset = vf_reset;
+            for _ in 0..4 {
+                cpu.execute_cycle(&mut bus);
+            }
+            assert_eq!(cpu.v[0x0], 0x0F);
+            assert_eq!(cpu.v[0xF], if vf_reset { 0 } else { 1 });
+        }
+    }
+
+    #[test]
+    fn draws_with_i_past_the_end_of_memory() {
+        // LD I, 0xFFF; LD V0, 2; ADD I, V0; DRW V1, V1, 5
+        let rom = [0xAF, 0xFF, 0x60, 0x02, 0xF0, 0x1E, 0xD1, 0x15];
+        let mut cpu = Cpu::new(&rom);
+        let mut bus = Bus::new();
+        for _ in 0..4 {
+            cpu.execute_cycle(&mut bus);
+        }
+        assert_eq!(cpu.pc, 0x208);
+        assert_eq!(cpu.v[0xF], 0);
+    }
+
+    #[test]
+    fn stores_exactly_the_requested_registers() {
+        // LD V0..V6 with 1..7; LD I, 0x300; LD [I], V5; LD V0, 0; LD Vx, [I] with V0
+        let rom = [
+            0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x63, 0x04, 0x64, 0x05, 0x65, 0x06, 0x66, 0x07,
+            0xA3, 0x00, 0xF5, 0x55, 0x60, 0x00, 0xF0, 0x65,
+        ];
+        let mut cpu = Cpu::new(&rom);
+        let mut bus = Bus::new();
+        for _ in 0..11 {
+            cpu.execute_cycle(&mut bus);
+        }
+        assert_eq!(cpu.ram.read_bytes(0x300, 0x307), &[1, 2, 3, 4, 5, 6, 0]);
+        assert_eq!(cpu.v[0], 1);
+    }
+
+    #[test]
+    fn stores_registers_up_to_the_end_of_memory() {
+        // LD I, 0xFFF; LD V0, 123; LD B, V0
+        let rom = [0xAF, 0xFF, 0x60, 0x7B, 0xF0, 0x33];
+        let mut cpu = Cpu::new(&rom);
+        let mut bus = Bus::new();
+        for _ in 0..3 {
+            cpu.execute_cycle(&mut bus);
+        }
+        assert_eq!(cpu.ram.read_byte(0xFFF), 1);
+        assert_eq!(cpu.pc, 0x206);
+
+        // LD I, 0xFFE; LD V0, 1; LD V1, 2; LD V2, 3; LD [I], V2; LD V0, 0; LD V1, 0; LD V2, 0
+        // LD Vx, [I] with V2
+        let rom = [
+            0xAF, 0xFE, 0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0xF2, 0x55, 0x60, 0x00, 0x61, 0x00,
+            0x62, 0x00, 0xF2, 0x65,
+        ];
+        let mut cpu = Cpu::new(&rom);
+        for _ in 0..9 {
+            cpu.execute_cycle(&mut bus);
+        }
+        assert_eq!(cpu.ram.read_bytes(0xFFE, 0x1000), &[1, 2]);
+        assert_eq!(&cpu.v[..3], &[1, 2, 0]);
+        assert_eq!(cpu.pc, 0x212);
+    }
+
+    #[test]
+    fn selects_all_four_planes() {
+        // PLANE 15
+        let mut cpu = Cpu::new(&[0xFF, 0x01]);
+        let mut bus = Bus::new();
+        cpu.execute_cycle(&mut bus);
+        assert_eq!(bus.display.plane_mask(), 0xF);
+        assert_eq!(bus.display.plane_count(), 4);
+    }
+
+    #[test]
+    fn runs_to_halt() {
+        // LD V0, 3; loop: ADD V0, -1 (0xFF); SE V0, 0; JP loop; end: JP end
+        let rom = [0x60, 0x03, 0x70, 0xFF, 0x30, 0x00, 0x12, 0x02, 0x12, 0x08];
+        let mut cpu = Cpu::new(&rom);
+        let mut bus = Bus::new();
+        // The load, two full loops, the last ADD and skipping SE, then the JP to itself
+        let outcome = cpu.run_to_halt(&mut bus, 100);
+        assert_eq!(
+            outcome,
+            RunOutcome {
+                halted: true,
+                cycles: 10
+            }
+        );
+
+        let mut cpu = Cpu::new(&rom[..8]);
+        let outcome = cpu.run_to_halt(&mut bus, 5);
+        assert_eq!(
+            outcome,
+            RunOutcome {
+                halted: false,
+                cycles: 5
+            }
+        );
+    }
+
+    #[test]
+    fn ret_with_an_empty_stack_is_illegal() {
+        let mut bus = Bus::new();
+        let mut cpu = Cpu::new(&[0x00, 0xEE]);
+        cpu.on_illegal = IllegalPolicy::Error;
+        assert_eq!(cpu.step(&mut bus), StepResult::IllegalOpcode(0x00EE));
+
+        let mut cpu = Cpu::new(&[0x00, 0xEE]);
+        assert_eq!(
+            cpu.run_to_halt(&mut bus, 10),
+            RunOutcome {
+                halted: true,
+                cycles: 1
+            }
+        );
+    }
+
+    #[test]
+    fn xo_chip_opcodes_need_xo_chip_mode() {
+        // LD [I], V0..V1; PLANE 1; AUDIO; PITCH V0
+        for &opcode in &[0x5012u16, 0xF101, 0xF002, 0xF03A] {
+            let [hi, lo] = opcode.to_be_bytes();
+            let mut bus = Bus::new();
+            let mut cpu = Cpu::new(&[hi, lo]);
+            cpu.mode = ChipMode::SuperChip;
+            cpu.on_illegal = IllegalPolicy::Error;
+            assert_eq!(cpu.step(&mut bus), StepResult::IllegalOpcode(opcode));
+
+            let mut cpu = Cpu::new(&[hi, lo]);
+            cpu.on_illegal = IllegalPolicy::Error;
+            assert_ne!(cpu.step(&mut bus), StepResult::IllegalOpcode(opcode));
+        }
+    }
+
+    #[test]
+    fn stops_at_breakpoints() {
+        // LD V0, 1; LD V1, 2; LD V2, 3; loop: JP loop
+        let rom = [0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x12, 0x06];
+        let mut cpu = Cpu::new(&rom);
+        let mut bus = Bus::new();
+        cpu.add_breakpoint(0x204);
+        assert_eq!(cpu.run_frame(&mut bus, 10), RunResult::BreakpointHit(0x204));
+        assert_eq!(cpu.pc, 0x204);
+        assert_eq!(cpu.v[2], 0);
+        // Resuming executes the instruction the breakpoint is on
+        assert_eq!(cpu.run_frame(&mut bus, 3), RunResult::Completed);
+        assert_eq!(cpu.v[2], 3);
+
+        let mut cpu = Cpu::new(&rom);
+        cpu.add_breakpoint(0x204);
+        cpu.remove_breakpoint(0x204);
+        assert_eq!(cpu.run_frame(&mut bus, 3), RunResult::Completed);
+        assert_eq!(cpu.v[2], 3);
+    }
+
+    #[test]
+    fn flags_frame_buffer_changes_once() {
+        // LD V0, 1; LD I, 0 (the font's 0); DRW V0, V0, 5
+        let rom = [0x60, 0x01, 0xA0, 0x00, 0xD0, 0x05];
+        let mut cpu = Cpu::new(&rom);
+        let mut bus = Bus::new();
+        assert!(!cpu.frame_buffer_changed());
+        for _ in 0..2 {
+            cpu.execute_cycle(&mut bus);
+        }
+        assert!(!cpu.frame_buffer_changed());
+        cpu.execute_cycle(&mut bus);
+        assert!(cpu.frame_buffer_changed());
+        assert!(!cpu.frame_buffer_changed());
+    }
+
+    #[test]
+    fn warns_once_when_running_past_the_program() {
+        // LD V0, 1; LD V1, 2
+        let rom = [0x60, 0x01, 0x61, 0x02];
+        let mut cpu = Cpu::new(&rom);
+        let mut bus = Bus::new();
+        cpu.warn_past_program = true;
+        assert_eq!(
+            cpu.run_frame(&mut bus, 10),
+            RunResult::ExecutedPastProgram(0x204)
+        );
+        assert_eq!(cpu.v[1], 2);
+        assert_eq!(cpu.pc, 0x204);
+        // Running again carries on past the end instead of warning again
+        assert_ne!(
+            cpu.run_frame(&mut bus, 1),
+            RunResult::ExecutedPastProgram(0x204)
+        );
+    }
+
+    #[test]
+    fn wraps_the_pc_around_the_end_of_memory() {
+        // JP 0xFFE, to an LD V0, 7 in the last two bytes of memory
+        let mut cpu = Cpu::new(&[0x1F, 0xFE]);
+        let mut bus = Bus::new();
+        cpu.ram.write_byte(0xFFE, 0x60);
+        cpu.ram.write_byte(0xFFF, 0x07);
+        for _ in 0..2 {
+            cpu.execute_cycle(&mut bus);
+        }
+        assert_eq!(cpu.v[0], 7);
+        assert_eq!(cpu.pc, 0x000);
+    }
+
+    #[test]
+    fn reports_the_side_effects_of_a_step() {
+        // CLS; LD V0, 1; LD V1, K; EXIT
+        let rom = [0x00, 0xE0, 0x60, 0x01, 0xF1, 0x0A, 0x00, 0xFD];
+        let mut cpu = Cpu::new(&rom);
+        let mut bus = Bus::new();
+        assert_eq!(cpu.step(&mut bus), StepResult::Redraw);
+        assert_eq!(cpu.step(&mut bus), StepResult::Continue);
+        assert_eq!(cpu.step(&mut bus), StepResult::WaitKey);
+        assert_eq!(cpu.step(&mut bus), StepResult::WaitKey);
+        bus.key_pressed = Some(5);
+        assert_eq!(cpu.step(&mut bus), StepResult::Continue);
+        assert_eq!(cpu.v[1], 5);
+        assert_eq!(cpu.step(&mut bus), StepResult::Halted);
+        assert_eq!(cpu.step(&mut bus), StepResult::Halted);
+    }
+
+    #[test]
+    fn applies_the_illegal_opcode_policy() {
+        // 8008 (no such ALU operation); LD V0, 5
+        let rom = [0x80, 0x08, 0x60, 0x05];
+        let mut bus = Bus::new();
+
+        let mut cpu = Cpu::new(&rom);
+        assert_eq!(cpu.step(&mut bus), StepResult::Halted);
+        assert_eq!(cpu.pc, 0x200);
+
+        let mut cpu = Cpu::new(&rom);
+        cpu.on_illegal = IllegalPolicy::Skip;
+        assert_eq!(cpu.step(&mut bus), StepResult::Continue);
+        cpu.step(&mut bus);
+        assert_eq!(cpu.v[0], 5);
+
+        let mut cpu = Cpu::new(&rom);
+        cpu.on_illegal = IllegalPolicy::Error;
+        assert_eq!(cpu.run_frame(&mut bus, 5), RunResult::IllegalOpcode(0x8008));
+        assert_eq!(cpu.pc, 0x200);
+        assert_eq!(cpu.step(&mut bus), StepResult::IllegalOpcode(0x8008));
+    }
+
+    #[test]
+    fn budgets_frames_by_machine_cycles() {
+        let mut bus = Bus::new();
+        // LD V0, 1; loop: ADD V0, 1; JP loop
+        let mut cheap = Cpu::new(&[0x60, 0x01, 0x70, 0x01, 0x12, 0x02]);
+        cheap.cycle_accurate = true;
+        cheap.run_frame(&mut bus, 100);
+        // 6 cycles for the load, then 22 per loop until the budget runs out during the fifth
+        assert_eq!(cheap.v[0], 6);
+
+        // LD V0, 1; DRW V0, V0, 5; JP 0x200
+        let rom = [0x60, 0x01, 0xD0, 0x05, 0x12, 0x00];
+        let mut expensive = Cpu::new(&rom);
+        expensive.cycle_accurate = true;
+        expensive.run_frame(&mut bus, 100);
+        assert_eq!(expensive.pc, 0x204);
+
+        let mut uniform = Cpu::new(&rom);
+        uniform.run_frame(&mut bus, 4);
+        assert_eq!(uniform.pc, 0x202);
+    }
+
+    #[test]
+    fn draws_at_the_active_resolution() {
+        // LD V0, 100; LD V1, 40; DRW V0, V1, 1 (top row of the font's 0)
+        let draw = [0x60, 100, 0x61, 40, 0xD0, 0x11];
+        let mut cpu = Cpu::new(&draw);
+        let mut bus = Bus::new();
+        for _ in 0..3 {
+            cpu.execute_cycle(&mut bus);
+        }
+        // Lo-res wraps the coordinates at 64x32
+        let frame = bus.display.get_frame_buffer();
+        assert_eq!(frame.len(), 64 * 32);
+        assert_eq!(frame[(40 % 32) * 64 + 100 % 64], 1);
+
+        // HIGH first
+        let mut rom = vec![0x00, 0xFF];
+        rom.extend_from_slice(&draw);
+        let mut cpu = Cpu::new(&rom);
+        let mut bus = Bus::new();
+        for _ in 0..4 {
+            cpu.execute_cycle(&mut bus);
+        }
+        let frame = bus.display.get_frame_buffer();
+        assert_eq!(frame.len(), 128 * 64);
+        assert_eq!(frame[40 * 128 + 100], 1);
+        assert_eq!(frame[8 * 128 + 36], 0);
+    }
+
+    #[test]
+    fn saves_and_restores_flag_registers() {
+        // LD V0..V3 with 1..4; LD R, V3; LD V0..V3 with 0; LD V3, R; LD R, VF
+        let rom = [
+            0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x63, 0x04, 0xF3, 0x75, 0x60, 0x00, 0x61, 0x00,
+            0x62, 0x00, 0x63, 0x00, 0xF3, 0x85, 0xFF, 0x75,
+        ];
+        let mut cpu = Cpu::new(&rom);
+        let mut bus = Bus::new();
+        for _ in 0..9 {
+            cpu.execute_cycle(&mut bus);
+        }
+        assert_eq!(&cpu.flags[..4], &[1, 2, 3, 4]);
+        assert_eq!(&cpu.v[..4], &[0, 0, 0, 0]);
+        cpu.execute_cycle(&mut bus);
+        assert_eq!(&cpu.v[..4], &[1, 2, 3, 4]);
+        // x is bounded to the 8 flag registers rather than panicking
+        cpu.execute_cycle(&mut bus);
+        assert_eq!(cpu.pc, 0x216);
+    }
+
+    #[test]
+    fn accessors_reflect_the_state() {
+        // LD V0, 7; LD I, 0x300; LD DT, V0; CALL 0x208; RET
+        let rom = [0x60, 0x07, 0xA3, 0x00, 0xF0, 0x15, 0x22, 0x08, 0x00, 0xEE];
+        let mut cpu = Cpu::new(&rom);
+        let mut bus = Bus::new();
+        for _ in 0..4 {
+            cpu.step(&mut bus);
+        }
+        assert_eq!(cpu.registers()[0], 7);
+        assert_eq!(cpu.index(), 0x300);
+        assert_eq!(cpu.delay_timer(), 7);
+        assert_eq!(cpu.sound_timer(), 0);
+        assert_eq!(cpu.sp(), 1);
+        assert_eq!(cpu.stack(), &[0x208]);
+        assert_eq!(cpu.program_counter(), 0x208);
+        assert_eq!(cpu.memory()[0x200], 0x60);
+        assert_eq!(cpu.ram().read_byte(0x201), 7);
+    }
+
+    #[test]
+    fn clears_vf_before_each_draw() {
+        // LD VF, 1; LD I, 0; DRW V0, V0, 5 three times
+        let rom = [0x6F, 0x01, 0xA0, 0x00, 0xD0, 0x05, 0xD0, 0x05, 0xD0, 0x05];
+        let mut cpu = Cpu::new(&rom);
+        let mut bus = Bus::new();
+        for _ in 0..3 {
+            cpu.execute_cycle(&mut bus);
+        }
+        assert_eq!(cpu.v[0xF], 0);
+        // Drawing over the sprite erases it, then drawing on the blank screen collides with nothing
+        cpu.execute_cycle(&mut bus);
+        assert_eq!(cpu.v[0xF], 1);
+        cpu.execute_cycle(&mut bus);
+        assert_eq!(cpu.v[0xF], 0);
+    }
+
+    #[test]
+    fn saves_and_loads_register_ranges_both_ways() {
+        // LD V1..V3 with 1..3; LD I, 0x300; LD [I], V1..V3; LD I, 0x310; LD [I], V3..V1
+        // LD V1..V3 with 0; LD I, 0x300; LD V1..V3, [I]
+        let rom = [
+            0x61, 0x01, 0x62, 0x02, 0x63, 0x03, 0xA3, 0x00, 0x51, 0x32, 0xA3, 0x10, 0x53, 0x12,
+            0x61, 0x00, 0x62, 0x00, 0x63, 0x00, 0xA3, 0x00, 0x51, 0x33,
+        ];
+        let mut cpu = Cpu::new(&rom);
+        let mut bus = Bus::new();
+        for _ in 0..7 {
+            cpu.execute_cycle(&mut bus);
+        }
+        assert_eq!(cpu.ram.read_bytes(0x300, 0x303), &[1, 2, 3]);
+        assert_eq!(cpu.ram.read_bytes(0x310, 0x313), &[3, 2, 1]);
+        // I is left alone
+        assert_eq!(cpu.i, 0x310);
+        for _ in 0..5 {
+            cpu.execute_cycle(&mut bus);
+        }
+        assert_eq!(&cpu.v[1..4], &[1, 2, 3]);
+
+        // LD I, 0x204; LD V3..V1, [I]; then the bytes 7, 8, 9
+        let mut cpu = Cpu::new(&[0xA2, 0x04, 0x53, 0x13, 0x07, 0x08, 0x09]);
+        for _ in 0..2 {
+            cpu.execute_cycle(&mut bus);
+        }
+        assert_eq!(&cpu.v[1..4], &[9, 8, 7]);
+
+        // LD I, 0xFFF; LD [I], V0..VF stops at the end of memory
+        let mut cpu = Cpu::new(&[0xAF, 0xFF, 0x50, 0xF2]);
+        for _ in 0..2 {
+            cpu.execute_cycle(&mut bus);
+        }
+        assert_eq!(cpu.pc, 0x204);
+    }
+
+    #[test]
+    fn beeps_with_the_loaded_audio_pattern() {
+        // LD I, 0x20A; AUDIO; LD V1, 0x70; PITCH V1; LD ST, V1; then the pattern 0..16
+        let mut rom = vec![0xA2, 0x0A, 0xF0, 0x02, 0x61, 0x70, 0xF1, 0x3A, 0xF1, 0x18];
+        rom.extend(0..16);
+        let mut cpu = Cpu::new(&rom);
+        let mut bus = Bus::new();
+        let mut pattern = [0; 16];
+        pattern.copy_from_slice(&rom[10..]);
+
+        assert!(cpu.beep_pattern().is_none());
+        for _ in 0..2 {
+            cpu.execute_cycle(&mut bus);
+        }
+        assert_eq!(cpu.audio_pattern(), Some(&pattern));
+        // Silent until the sound timer is set
+        assert!(cpu.beep_pattern().is_none());
+        for _ in 0..3 {
+            cpu.execute_cycle(&mut bus);
+        }
+        assert_eq!(cpu.pitch(), 0x70);
+        assert_eq!(cpu.beep_pattern(), Some((&pattern, 0x70)));
+    }
+}
@@ -1,18 +1,426 @@
+use crate::opcodes::{self, ChipMode};
 use crate::ram::Ram;
-use std::fs::File;
-use std::io::{Read, Write};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
 use std::path::Path;
 
 const START_ROM: usize = 512; // 0x200
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    // Plain `.chasm` assembly, one instruction per line
+    Text,
+    // JSON array of `DisassembledLine`
+    Json,
+    // C array of the raw ROM bytes, for embedding the ROM in another program
+    ByteArray,
+}
+
+// Encoding of the ROM file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    // Raw `.ch8` bytes
+    Binary,
+    // Hex text, either continuous or split over lines (e.g. one opcode per line)
+    Hex,
+}
+
+// Radix used for addresses and operands in the listing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Radix {
+    Decimal,
+    Hex,
+}
+
+// Indentation of instructions in the text output, labels always stay flush-left
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Indent {
+    None,
+    Spaces(usize),
+    Tab,
+}
+
+// Options of the disassembler, built up from the defaults:
+// DisassemblerConfig::default().radix(Radix::Hex).verbose(true)
+#[derive(Debug, Clone)]
+pub struct DisassemblerConfig {
+    pub format: OutputFormat,
+    pub radix: Radix,
+    pub verbose: bool,
+    pub labels: bool,
+    pub strict_align: bool,
+    pub quiet: bool,
+    pub output_path: Option<String>,
+    pub skip_bytes: usize,
+    pub indent: Indent,
+    // Pad mnemonics so operands line up in a column
+    pub align_operands: bool,
+    // Symbol name of the `ByteArray` output
+    pub array_name: String,
+    pub input_format: InputFormat,
+    pub max_instructions: Option<usize>,
+    // Name V10-V15 as VA-VF
+    pub hex_registers: bool,
+}
+
+impl DisassemblerConfig {
+    pub fn format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn radix(mut self, radix: Radix) -> Self {
+        self.radix = radix;
+        self
+    }
+
+    // Comment every instruction with its description from the opcode table
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    // Generate `L_xxxx` labels for jump/call targets and use them as operands
+    pub fn labels(mut self, labels: bool) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    // Error out if control flow reaches an odd address instead of silently decoding at even offsets
+    pub fn strict_align(mut self, strict_align: bool) -> Self {
+        self.strict_align = strict_align;
+        self
+    }
+
+    // Don't print the listing to stdout
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    // Write the disassembly to an explicit path instead of deriving it from the ROM name
+    pub fn output_path(mut self, output_path: String) -> Self {
+        self.output_path = Some(output_path);
+        self
+    }
+
+    // Skip a header of this many bytes at the start of the ROM file
+    pub fn skip_bytes(mut self, skip_bytes: usize) -> Self {
+        self.skip_bytes = skip_bytes;
+        self
+    }
+
+    pub fn indent(mut self, indent: Indent) -> Self {
+        self.indent = indent;
+        self
+    }
+
+    pub fn align_operands(mut self, align_operands: bool) -> Self {
+        self.align_operands = align_operands;
+        self
+    }
+
+    pub fn array_name(mut self, array_name: String) -> Self {
+        self.array_name = array_name;
+        self
+    }
+
+    pub fn input_format(mut self, input_format: InputFormat) -> Self {
+        self.input_format = input_format;
+        self
+    }
+
+    pub fn hex_registers(mut self, hex_registers: bool) -> Self {
+        self.hex_registers = hex_registers;
+        self
+    }
+
+    // Stop decoding after this many instructions, e.g. to preview the start of a large ROM
+    pub fn max_instructions(mut self, max_instructions: usize) -> Self {
+        self.max_instructions = Some(max_instructions);
+        self
+    }
+}
+
+impl Default for DisassemblerConfig {
+    fn default() -> Self {
+        Self {
+            format: OutputFormat::Text,
+            radix: Radix::Decimal,
+            verbose: false,
+            labels: false,
+            strict_align: false,
+            quiet: false,
+            output_path: None,
+            skip_bytes: 0,
+            indent: Indent::None,
+            align_operands: false,
+            array_name: String::from("rom"),
+            input_format: InputFormat::Binary,
+            max_instructions: None,
+            hex_registers: false,
+        }
+    }
+}
+
+// Structured form of a decoded instruction, used for the JSON output
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DisassembledLine {
+    pub address: u16,
+    pub opcode: u16,
+    pub mnemonic: String,
+    pub operands: Vec<String>,
+}
+
+impl DisassembledLine {
+    fn new(address: usize, opcode: u16, instruction: &str) -> Self {
+        // Drop any trailing comment, then split `MNEMONIC op1, op2`
+        let code = instruction.split(';').next().unwrap_or("").trim();
+        let (mnemonic, operands) = match code.find(' ') {
+            Some(idx) => (&code[..idx], &code[idx + 1..]),
+            None => (code, ""),
+        };
+
+        Self {
+            address: address as u16,
+            opcode,
+            mnemonic: mnemonic.to_string(),
+            operands: operands
+                .split(&[',', ' '][..])
+                .filter(|operand| !operand.is_empty())
+                .map(String::from)
+                .collect(),
+        }
+    }
+}
+
+// How a ROM uses one of the V registers
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RegUsage {
+    pub read: bool,
+    pub written: bool,
+}
+
+impl RegUsage {
+    pub fn is_unused(&self) -> bool {
+        !self.read && !self.written
+    }
+}
+
+// Bitmask of the registers Vx through Vy (in either order), as used by the range opcodes
+fn register_range(x: usize, y: usize) -> u16 {
+    (x.min(y)..=x.max(y)).fold(0, |mask, reg| mask | 1 << reg)
+}
+
+// Style of the rendered instructions:
+// RenderOpts::default().radix(Radix::Hex).hex_registers(true)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderOpts {
+    // Radix of the immediate and address operands
+    pub radix: Radix,
+    // Name registers 10-15 VA-VF rather than V10-V15
+    pub hex_registers: bool,
+}
+
+impl RenderOpts {
+    pub fn radix(mut self, radix: Radix) -> Self {
+        self.radix = radix;
+        self
+    }
+
+    pub fn hex_registers(mut self, hex_registers: bool) -> Self {
+        self.hex_registers = hex_registers;
+        self
+    }
+
+    fn register(&self, register: usize) -> String {
+        if self.hex_registers {
+            format!("V{:X}", register)
+        } else {
+            format!("V{}", register)
+        }
+    }
+
+    // In hex, padded to the width of the operand: 1 digit for a nibble, 2 for a byte and 3
+    // for an address
+    fn number(&self, value: u16, digits: usize) -> String {
+        match self.radix {
+            Radix::Decimal => value.to_string(),
+            Radix::Hex => format!("0x{:0width$x}", value, width = digits),
+        }
+    }
+}
+
+impl Default for RenderOpts {
+    fn default() -> Self {
+        Self {
+            radix: Radix::Decimal,
+            hex_registers: false,
+        }
+    }
+}
+
+// A fetched instruction word, classified for the control flow analysis
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decoded {
+    pub address: u16,
+    pub opcode: u16,
+}
+
+impl Decoded {
+    pub fn new(address: u16, opcode: u16) -> Self {
+        Self { address, opcode }
+    }
+
+    // Assembly text of the instruction, e.g. `DRW VA, VB, 0x5` or `DRW V10, V11, 5`
+    pub fn render(&self, opts: &RenderOpts) -> String {
+        render_opcode(self.opcode, opts).into_owned()
+    }
+
+    // Jumps, calls, returns, skips and exit
+    pub fn is_control_flow(&self) -> bool {
+        match self.opcode & 0xF000 {
+            0x0000 => matches!(self.opcode, 0x00EE | 0x00FD),
+            0x1000 | 0x2000 | 0x3000 | 0x4000 | 0x5000 | 0x9000 | 0xB000 => true,
+            0xE000 => matches!(self.opcode & 0x00FF, 0x9E | 0xA1),
+            _ => false,
+        }
+    }
+
+    // Execution never falls through to the next instruction: JP, RET and EXIT
+    pub fn is_terminator(&self) -> bool {
+        matches!(self.opcode, 0x00EE | 0x00FD) || matches!(self.opcode & 0xF000, 0x1000 | 0xB000)
+    }
+
+    // Bitmasks (bit n for Vn) of the registers this instruction reads and writes
+    pub fn registers(&self) -> (u16, u16) {
+        let x = (self.opcode >> 8 & 0xF) as usize;
+        let y = (self.opcode >> 4 & 0xF) as usize;
+        let (vx, vy, vf) = (1 << x, 1 << y, 1 << 0xF);
+        match (self.opcode & 0xF000, self.opcode & 0x00FF) {
+            (0x3000, _) | (0x4000, _) => (vx, 0),        // SE/SNE Vx, byte
+            (0x5000, 0x02) => (register_range(x, y), 0), // XO-CHIP save Vx - Vy
+            (0x5000, 0x03) => (0, register_range(x, y)), // XO-CHIP load Vx - Vy
+            (0x5000, _) | (0x9000, _) => (vx | vy, 0),   // SE/SNE Vx, Vy
+            (0x6000, _) => (0, vx),                      // LD Vx, byte
+            (0x7000, _) => (vx, vx),                     // ADD Vx, byte
+            (0x8000, _) => match self.opcode & 0x000F {
+                0x0 => (vy, vx),            // LD Vx, Vy
+                0x1..=0x3 => (vx | vy, vx), // OR/AND/XOR
+                _ => (vx | vy, vx | vf),    // ADD/SUB/SHR/SUBN/SHL set VF
+            },
+            (0xB000, _) => (1, 0),        // JP V0, addr
+            (0xC000, _) => (0, vx),       // RND Vx, byte
+            (0xD000, _) => (vx | vy, vf), // DRW sets the collision flag
+            (0xE000, _) => (vx, 0),       // SKP/SKNP Vx
+            (0xF000, 0x07) | (0xF000, 0x0A) => (0, vx),
+            (0xF000, 0x55) | (0xF000, 0x75) => (register_range(0, x), 0),
+            (0xF000, 0x65) | (0xF000, 0x85) => (0, register_range(0, x)),
+            (0xF000, 0x15) | (0xF000, 0x18) | (0xF000, 0x1E) | (0xF000, 0x29) => (vx, 0),
+            (0xF000, 0x30) | (0xF000, 0x33) | (0xF000, 0x3A) => (vx, 0),
+            _ => (0, 0),
+        }
+    }
+
+    // Addresses execution can continue at after this instruction
+    pub(crate) fn successors(&self) -> Vec<usize> {
+        let address = self.address as usize;
+        let nnn = (self.opcode & 0x0FFF) as usize;
+        match self.opcode & 0xF000 {
+            0x1000 => vec![nnn],              // JP addr
+            0x2000 => vec![nnn, address + 2], // CALL addr
+            // RET resumes after the matching CALL, JP V0, addr depends on V0
+            _ if self.is_terminator() => vec![],
+            _ if self.is_control_flow() => vec![address + 2, address + 4], // Skips
+            _ => vec![address + 2],
+        }
+    }
+}
+
+// Straight-line run of reachable instructions, entered only at `start` and left only after
+// `terminator`, its last instruction; `end` is the address just past it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BasicBlock {
+    pub start: u16,
+    pub end: u16,
+    pub terminator: Decoded,
+}
+
+// Address in a symbol file, hexadecimal with a `0x` prefix or decimal
+fn parse_address(word: &str) -> Option<usize> {
+    match word.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => word.parse().ok(),
+    }
+}
+
+// Decode a hex text ROM, ignoring whitespace and `0x`/`0X` prefixes
+fn decode_hex_text(text: &[u8]) -> Result<Vec<u8>, hex::FromHexError> {
+    let digits: String = String::from_utf8_lossy(text)
+        .split_whitespace()
+        .map(|word| {
+            word.strip_prefix("0x")
+                .or_else(|| word.strip_prefix("0X"))
+                .unwrap_or(word)
+        })
+        .collect();
+    hex::decode(digits)
+}
+
+// Verbose comment spelling out which way a timer opcode copies its value
+fn timer_comment(opcode: u16, opts: &RenderOpts) -> Option<String> {
+    let vx = opts.register(((opcode & 0x0F00) >> 8) as usize);
+    match opcode & 0xF0FF {
+        0xF007 => Some(format!("read delay timer into {}", vx)),
+        0xF015 => Some(format!("set delay timer from {}", vx)),
+        0xF018 => Some(format!(
+            "set sound timer from {}, beeping while non-zero",
+            vx
+        )),
+        _ => None,
+    }
+}
+
+// Ends a listing cut short by `max_instructions`
+const TRUNCATED_MARKER: &str = "; ... truncated";
+
+// Undecodable opcodes are written as data, so they reassemble to the same bytes
+fn data_directive(opcode: u16) -> String {
+    format!("DB 0x{:02X}, 0x{:02X}", opcode >> 8, opcode & 0x00FF)
+}
+
+// Control flow reaching an odd address, which means data is likely being decoded as code
+#[derive(Debug)]
+pub struct AlignmentError {
+    pub source: usize,
+    pub target: usize,
+}
+
+impl fmt::Display for AlignmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Instruction at [{}] leads to odd address [{}]",
+            self.source, self.target
+        )
+    }
+}
+
 pub struct Disassembler {
     pub ram: Ram,
     rom_size: usize,
     rom_path: String,
+    config: DisassemblerConfig,
+    // Names loaded from a symbol file, used instead of generated labels
+    symbols: BTreeMap<usize, String>,
 }
 
 impl Disassembler {
-    pub fn new(rom_path: String) -> Self {
+    pub fn new(rom_path: String, config: DisassemblerConfig) -> Self {
         let mut rom_buffer = Vec::<u8>::new();
         let mut file = File::open(&rom_path).expect("File not found");
 
@@ -21,50 +429,547 @@ impl Disassembler {
         } else {
             println!("Error loading ROM");
         };
+        if config.input_format == InputFormat::Hex {
+            rom_buffer = match decode_hex_text(&rom_buffer) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    println!("Error decoding hex ROM: {}", e);
+                    Vec::new()
+                }
+            };
+        }
+        // Ignore any container header before the program bytes
+        let program_start = config.skip_bytes.min(rom_buffer.len());
 
         Self {
-            ram: Ram::new(&rom_buffer),
-            rom_size: rom_buffer.len() + START_ROM,
+            ram: Ram::new(&rom_buffer[program_start..]),
+            rom_size: rom_buffer.len() - program_start + START_ROM,
             rom_path,
+            config,
+            symbols: BTreeMap::new(),
+        }
+    }
+
+    // Load a symbol file with one `name address` pair per line (`;` starts a comment), e.g.
+    // `main 0x204` or `main = 0x204`; the names label those addresses in the output
+    pub fn load_symbols(&mut self, path: &str) -> io::Result<()> {
+        let text = fs::read_to_string(path)?;
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            // `name = address`, as exported by the assembler, is accepted too
+            let mut words = line.split_whitespace().filter(|&word| word != "=");
+            match (
+                words.next(),
+                words.next().and_then(parse_address),
+                words.next(),
+            ) {
+                (Some(name), Some(address), None) => {
+                    self.symbols
+                        .insert(address, name.trim_end_matches(':').to_string());
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid symbol on line {}: {}", idx + 1, line),
+                    ))
+                }
+            }
         }
+        Ok(())
+    }
+
+    pub fn config(&self) -> &DisassemblerConfig {
+        &self.config
+    }
+
+    // Labels generated for jump/call targets and loaded symbols, by name
+    pub fn symbol_table(&self) -> HashMap<String, u16> {
+        let mut labels = self.label_pass();
+        labels.extend(self.decoded_symbols());
+        labels
+            .into_iter()
+            .map(|(address, label)| (label, address as u16))
+            .collect()
     }
 
     pub fn run(&self) {
-        let mut opcode_buffer = Vec::new();
-        println!("Address  Opcode  Instruction");  
-        for idx in START_ROM..self.rom_size {
+        if let Err(e) = self.walk_control_flow() {
+            println!("Error: {}", e);
+            return;
+        }
+
+        let overlaps = self.code_data_overlaps();
+        if !overlaps.is_empty() {
+            let addresses: Vec<String> = overlaps.iter().map(|a| format!("{:#05x}", a)).collect();
+            println!(
+                "Warning: addresses reached as code are also LD I data targets: {}",
+                addresses.join(", ")
+            );
+        }
+        for (source, target) in self.misaligned_targets() {
+            println!(
+                "Warning: jump at {:#05x} targets {:#05x}, which isn't the start of an instruction",
+                source, target
+            );
+        }
+
+        let labels = self.labels();
+        let decoded = self.decode_all(&labels);
+        if !self.config.quiet {
+            for line in self.listing_lines(&decoded, &labels) {
+                println!("{}", line);
+            }
+        }
+        match self.write_file(&decoded, &labels) {
+            Ok(path) => println!("File disassembled: {}", path),
+            Err(e) => println!("Error: {}", e),
+        }
+    }
+
+    // Addresses at which a byte sequence occurs in the ROM
+    pub fn find(&self, pattern: &[u8]) -> Vec<u16> {
+        if pattern.is_empty() {
+            return Vec::new();
+        }
+        self.ram.memory[START_ROM..self.rom_size]
+            .windows(pattern.len())
+            .enumerate()
+            .filter(|(_, window)| *window == pattern)
+            .map(|(offset, _)| (START_ROM + offset) as u16)
+            .collect()
+    }
+
+    // Addresses of the instructions matching `opcode` on the bits set in `mask`,
+    // e.g. every DRW with opcode 0xD000 and mask 0xF000
+    pub fn find_opcode(&self, opcode: u16, mask: u16) -> Vec<u16> {
+        (START_ROM..self.rom_size.saturating_sub(1))
+            .step_by(2)
+            .filter(|&address| self.fetch_op(address) & mask == opcode & mask)
+            .map(|address| address as u16)
+            .collect()
+    }
+
+    // `LD I` targets pointing into reachable code, which means self-modifying code or
+    // data being decoded as instructions
+    pub fn code_data_overlaps(&self) -> Vec<u16> {
+        let reachable = match self.walk_control_flow() {
+            Ok(reachable) => reachable,
+            Err(_) => return Vec::new(),
+        };
+        let targets: BTreeSet<usize> = reachable
+            .iter()
+            .map(|&address| self.fetch_op(address))
+            .filter(|opcode| opcode & 0xF000 == 0xA000)
+            .map(|opcode| (opcode & 0x0FFF) as usize)
+            .collect();
+        targets
+            .into_iter()
+            .filter(|&target| {
+                reachable.contains(&target) || target > 0 && reachable.contains(&(target - 1))
+            })
+            .map(|target| target as u16)
+            .collect()
+    }
+
+    // Which of V0-VF the reachable instructions read and write, or every decoded word if the
+    // control flow can't be followed
+    pub fn register_usage(&self) -> [RegUsage; 16] {
+        let addresses: Vec<usize> = match self.walk_control_flow() {
+            Ok(reachable) => reachable.into_iter().collect(),
+            Err(_) => (START_ROM..self.rom_size.saturating_sub(1))
+                .step_by(2)
+                .collect(),
+        };
+        let mut usage = [RegUsage::default(); 16];
+        for address in addresses {
+            let (reads, writes) = Decoded::new(address as u16, self.fetch_op(address)).registers();
+            for (reg, usage) in usage.iter_mut().enumerate() {
+                usage.read |= reads & 1 << reg != 0;
+                usage.written |= writes & 1 << reg != 0;
+            }
+        }
+        usage
+    }
+
+    // Addresses the control flow analysis starts from: the start of the ROM and, when the ROM
+    // opens with a jump over a header or data, the jump target
+    pub fn entry_points(&self) -> Vec<u16> {
+        let mut entry_points = vec![START_ROM as u16];
+        if self.is_decoded_address(START_ROM) {
+            let opcode = self.fetch_op(START_ROM);
+            let target = opcode & 0x0FFF;
+            if opcode & 0xF000 == 0x1000 && target as usize != START_ROM {
+                entry_points.push(target);
+            }
+        }
+        entry_points
+    }
+
+    // Reachable code split into basic blocks, in address order. A block ends at a control flow
+    // instruction or right before the target of one, so CALL and skips end blocks too.
+    pub fn basic_blocks(&self) -> Vec<BasicBlock> {
+        let reachable = match self.walk_control_flow() {
+            Ok(reachable) => reachable,
+            Err(_) => return Vec::new(),
+        };
+        let decoded: Vec<Decoded> = reachable
+            .iter()
+            .map(|&address| Decoded::new(address as u16, self.fetch_op(address)))
+            .collect();
+        let mut leaders: BTreeSet<usize> = decoded
+            .iter()
+            .filter(|instruction| instruction.is_control_flow())
+            .flat_map(|instruction| instruction.successors())
+            .collect();
+        leaders.insert(START_ROM);
+
+        let mut blocks = Vec::new();
+        let mut start = None;
+        for instruction in decoded {
+            let address = instruction.address as usize;
+            let block_start = *start.get_or_insert(address);
+            let next = address + 2;
+            if instruction.is_control_flow()
+                || leaders.contains(&next)
+                || !reachable.contains(&next)
+            {
+                blocks.push(BasicBlock {
+                    start: block_start as u16,
+                    end: next as u16,
+                    terminator: instruction,
+                });
+                start = None;
+            }
+        }
+        blocks
+    }
+
+    // (source, target) of the reachable JP/CALL instructions whose target isn't the start of an
+    // instruction: an odd address, or the second byte of a reachable instruction. This is almost
+    // always a bug, or self-modifying code.
+    pub fn misaligned_targets(&self) -> Vec<(u16, u16)> {
+        let reachable = match self.walk_control_flow() {
+            Ok(reachable) => reachable,
+            Err(_) => return Vec::new(),
+        };
+        reachable
+            .iter()
+            .map(|&address| (address, self.fetch_op(address)))
+            .filter(|(_, opcode)| matches!(opcode & 0xF000, 0x1000 | 0x2000))
+            .map(|(address, opcode)| (address, (opcode & 0x0FFF) as usize))
+            .filter(|&(_, target)| {
+                target & 1 == 1 || target > 0 && reachable.contains(&(target - 1))
+            })
+            .map(|(address, target)| (address as u16, target as u16))
+            .collect()
+    }
+
+    // Whether every reachable opcode of the ROM is legal on `mode`
+    pub fn is_compatible_with(&self, mode: ChipMode) -> bool {
+        self.incompatible_addresses(mode).is_empty()
+    }
+
+    // Addresses of the reachable opcodes `mode` doesn't support, or of any such word if
+    // control flow can't be followed
+    pub fn incompatible_addresses(&self, mode: ChipMode) -> Vec<u16> {
+        let addresses: Vec<usize> = match self.walk_control_flow() {
+            Ok(reachable) => reachable.into_iter().collect(),
+            Err(_) => (START_ROM..self.rom_size.saturating_sub(1))
+                .step_by(2)
+                .collect(),
+        };
+        addresses
+            .into_iter()
+            .filter(
+                |&address| match opcodes::required_mode(self.fetch_op(address)) {
+                    Some(required) => required > mode,
+                    None => true,
+                },
+            )
+            .map(|address| address as u16)
+            .collect()
+    }
+
+    // The listing printed by run(): address, opcode and instruction of every decoded word
+    pub fn listing(&self) -> Vec<String> {
+        let labels = self.labels();
+        let decoded = self.decode_all(&labels);
+        self.listing_lines(&decoded, &labels)
+    }
+
+    // Generated labels (when enabled), overridden by any loaded symbol at the same address
+    fn labels(&self) -> BTreeMap<usize, String> {
+        let mut labels = if self.config.labels {
+            self.label_pass()
+        } else {
+            BTreeMap::new()
+        };
+        labels.extend(self.decoded_symbols());
+        labels
+    }
+
+    // Loaded symbols that name a decoded address, the only places a label can be written
+    fn decoded_symbols(&self) -> impl Iterator<Item = (usize, String)> + '_ {
+        self.symbols
+            .iter()
+            .filter(move |(address, _)| self.is_decoded_address(**address))
+            .map(|(address, name)| (*address, name.clone()))
+    }
+
+    fn decode_all(&self, labels: &BTreeMap<usize, String>) -> Vec<(usize, u16, Cow<'static, str>)> {
+        let mut decoded = Vec::new();
+        let padding_start = self.padding_start(labels);
+        for idx in START_ROM..padding_start {
             // Check opcodes only at even addresses to prevent overflow
             // Possible problems since some ROMs include binary data at various addresses
-            if idx & 1 == 0 && idx + 1 < self.rom_size {
+            if idx & 1 == 0 && idx + 1 < padding_start {
+                if self.config.max_instructions == Some(decoded.len()) {
+                    return decoded;
+                }
                 let opcode = self.fetch_op(idx);
-                let instruction = self.decode_op(opcode);
-                println!("[{}]    {:04x}    {}", idx, opcode, instruction);
-                opcode_buffer.push(instruction);
+                let mut instruction = self.decode_labeled(opcode, labels);
+                if self.config.verbose && !instruction.contains(';') {
+                    let comment = timer_comment(opcode, &self.render_opts()).or_else(|| {
+                        opcodes::lookup(opcode).map(|spec| spec.description.to_string())
+                    });
+                    if let Some(comment) = comment {
+                        instruction = Cow::Owned(format!("{} ; {}", instruction, comment));
+                    }
+                }
+                decoded.push((idx, opcode, instruction));
             }
         }
-        match self.write_file(opcode_buffer) {
-            Ok(path) => println!("File disassembled: {}", path),
-            Err(e) => println!("Error: {}", e),
+        if self.config.max_instructions == Some(decoded.len()) {
+            return decoded;
         }
+        if padding_start < self.rom_size {
+            // Trailing zero padding is folded into one line, still reassembling to the same bytes
+            let size = self.rom_size - padding_start;
+            decoded.push((
+                padding_start,
+                0,
+                Cow::Owned(format!(
+                    "DB {} ; {} bytes of padding",
+                    vec!["0"; size].join(", "),
+                    size
+                )),
+            ));
+        } else if self.rom_size > START_ROM && (self.rom_size - START_ROM) % 2 == 1 {
+            // Keep the last byte of an odd-sized ROM so the output reassembles to the same bytes
+            let byte = self.ram.memory[self.rom_size - 1];
+            decoded.push((
+                self.rom_size - 1,
+                byte as u16,
+                Cow::Owned(format!("DB 0x{:02X}", byte)),
+            ));
+        }
+        decoded
     }
 
-    fn write_file(&self, buffer: Vec<String>) -> std::io::Result<String> {
-        let file_name = self.parse_path();
+    fn listing_lines(
+        &self,
+        decoded: &[(usize, u16, Cow<'static, str>)],
+        labels: &BTreeMap<usize, String>,
+    ) -> Vec<String> {
+        let mut lines = vec![String::from("Address  Opcode  Instruction")];
+        for (address, opcode, instruction) in decoded {
+            if let Some(label) = labels.get(address) {
+                lines.push(format!("{}:", label));
+            }
+            let address = match self.config.radix {
+                Radix::Decimal => format!("{}", address),
+                Radix::Hex => format!("0x{:03x}", address),
+            };
+            lines.push(format!(
+                "[{}]    {:04x}    {}",
+                address, opcode, instruction
+            ));
+        }
+        if self.is_truncated(labels) {
+            lines.push(String::from(TRUNCATED_MARKER));
+        }
+        lines
+    }
+
+    // Whether `max_instructions` cut the decoding short; a trailing odd byte counts as one
+    fn is_truncated(&self, labels: &BTreeMap<usize, String>) -> bool {
+        let padding_start = self.padding_start(labels);
+        let mut count = (padding_start - START_ROM).div_ceil(2);
+        if padding_start < self.rom_size {
+            count += 1;
+        }
+        self.config.max_instructions.is_some_and(|max| max < count)
+    }
+
+    // Start of the zero bytes that pad the end of many ROMs to a block size, at an even address
+    // so no instruction is split and past any label (such as a zeroed buffer); the end of the
+    // ROM if there are fewer than 4 of them
+    fn padding_start(&self, labels: &BTreeMap<usize, String>) -> usize {
+        let rom = &self.ram.memory[START_ROM..self.rom_size];
+        let zeros = rom.iter().rev().take_while(|&&byte| byte == 0).count();
+        let mut start = self.rom_size - zeros;
+        if let Some((&label, _)) = labels.range(start..).next_back() {
+            start = label + 1;
+        }
+        let start = (start + 1) & !1;
+        if self.rom_size.saturating_sub(start) >= 4 {
+            start
+        } else {
+            self.rom_size
+        }
+    }
+
+    fn write_file(
+        &self,
+        decoded: &[(usize, u16, Cow<'static, str>)],
+        labels: &BTreeMap<usize, String>,
+    ) -> std::io::Result<String> {
+        let file_name = match &self.config.output_path {
+            Some(output_path) => output_path.clone(),
+            None => self.parse_path(),
+        };
         let path = Path::new(&file_name);
         let display = path.display();
 
-        let mut file = match File::create(&path) {
+        if is_same_file(path, Path::new(&self.rom_path)) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "Output file {} would overwrite the input ROM, use -o to choose another path",
+                    display
+                ),
+            ));
+        }
+
+        let mut file = match File::create(path) {
             Err(e) => panic!("Couldn't create {}: {}", display, e),
             Ok(file) => file,
         };
 
-        writeln!(file, "{}", buffer.join("\n"))?;
+        match self.config.format {
+            OutputFormat::Text => {
+                for (address, _, inst) in decoded {
+                    if let Some(label) = labels.get(address) {
+                        writeln!(file, "{}:", label)?;
+                    }
+                    writeln!(file, "{}", self.format_instruction(inst))?;
+                }
+                if self.is_truncated(labels) {
+                    writeln!(file, "{}", TRUNCATED_MARKER)?;
+                }
+            }
+            OutputFormat::Json => {
+                let lines: Vec<DisassembledLine> = decoded
+                    .iter()
+                    .map(|(address, opcode, inst)| DisassembledLine::new(*address, *opcode, inst))
+                    .collect();
+                serde_json::to_writer_pretty(&mut file, &lines)?;
+                writeln!(file)?;
+            }
+            OutputFormat::ByteArray => write!(file, "{}", self.byte_array())?,
+        }
         Ok(file_name)
     }
 
+    // The ROM bytes as a C array, 12 bytes per line
+    fn byte_array(&self) -> String {
+        let rom = &self.ram.memory[START_ROM..self.rom_size];
+        let mut text = format!("const unsigned char {}[] = {{\n", self.config.array_name);
+        for row in rom.chunks(12) {
+            let bytes: Vec<String> = row.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+            text.push_str(&format!("    {},\n", bytes.join(", ")));
+        }
+        text.push_str("};\n");
+        text
+    }
+
+    // Apply the indentation and operand alignment of the text output
+    fn format_instruction(&self, instruction: &str) -> String {
+        let indent = match self.config.indent {
+            Indent::None => String::new(),
+            Indent::Spaces(count) => " ".repeat(count),
+            Indent::Tab => String::from("\t"),
+        };
+        match instruction.find(' ') {
+            // Width of the longest mnemonic (SKNP, SUBN) plus a space
+            Some(idx) if self.config.align_operands => format!(
+                "{}{:<5}{}",
+                indent,
+                &instruction[..idx],
+                &instruction[idx + 1..]
+            ),
+            _ => format!("{}{}", indent, instruction),
+        }
+    }
+
     fn parse_path(&self) -> String {
         let file_name: Vec<_> = self.rom_path.split(".ch8").collect();
-        return format!("{}.chasm", file_name[0]);
+        let extension = match self.config.format {
+            OutputFormat::Text => "chasm",
+            OutputFormat::Json => "json",
+            OutputFormat::ByteArray => "h",
+        };
+        format!("{}.{}", file_name[0], extension)
+    }
+
+    // Name every decoded address targeted by a JP, CALL or JP V0
+    fn label_pass(&self) -> BTreeMap<usize, String> {
+        let mut labels = BTreeMap::new();
+        for idx in (START_ROM..self.rom_size.saturating_sub(1)).step_by(2) {
+            let opcode = self.fetch_op(idx);
+            let target = (opcode & 0x0FFF) as usize;
+            let is_branch = matches!(opcode & 0xF000, 0x1000 | 0x2000 | 0xB000);
+            if is_branch && self.is_decoded_address(target) {
+                labels.insert(target, format!("L_{:04X}", target));
+            }
+        }
+        labels
+    }
+
+    // Whether an instruction is decoded at this address (even offsets inside the ROM)
+    fn is_decoded_address(&self, address: usize) -> bool {
+        address >= START_ROM && address & 1 == 0 && address + 1 < self.rom_size
+    }
+
+    // Decode an opcode, using the label of its target address when there is one
+    fn decode_labeled(&self, opcode: u16, labels: &BTreeMap<usize, String>) -> Cow<'static, str> {
+        let target = (opcode & 0x0FFF) as usize;
+        match (opcode & 0xF000, labels.get(&target)) {
+            (0x1000, Some(label)) => Cow::Owned(format!("JP {}", label)),
+            (0x2000, Some(label)) => Cow::Owned(format!("CALL {}", label)),
+            (0xB000, Some(label)) => Cow::Owned(format!("JP V0, {}", label)),
+            _ => self.decode_op(opcode),
+        }
+    }
+
+    // Follow the control flow from the entry points, returning every reachable instruction address
+    fn walk_control_flow(&self) -> Result<BTreeSet<usize>, AlignmentError> {
+        let mut reachable = BTreeSet::new();
+        let mut pending: Vec<(usize, usize)> = self
+            .entry_points()
+            .into_iter()
+            .map(|address| (START_ROM, address as usize))
+            .collect();
+
+        while let Some((source, address)) = pending.pop() {
+            if address < START_ROM || address + 1 >= self.rom_size || !reachable.insert(address) {
+                continue;
+            }
+            if self.config.strict_align && address & 1 == 1 {
+                return Err(AlignmentError {
+                    source,
+                    target: address,
+                });
+            }
+            let decoded = Decoded::new(address as u16, self.fetch_op(address));
+            for next in decoded.successors() {
+                pending.push((address, next));
+            }
+        }
+        Ok(reachable)
     }
 
     fn fetch_op(&self, idx: usize) -> u16 {
@@ -73,68 +978,535 @@ impl Disassembler {
         (hi_byte as u16) << 8 | lo_byte as u16
     }
 
-    fn decode_op(&self, opcode: u16) -> String {
-        let nibbles = (
-            ((opcode & 0xF000) >> 12) as u8,
-            ((opcode & 0x0F00) >> 8) as u8,
-            ((opcode & 0x00F0) >> 4) as u8,
-            (opcode & 0x000F) as u8,
-        );
-
-        let x = nibbles.1 as usize;
-        let y = nibbles.2 as usize;
-        let n = nibbles.3 as u8;
-        let kk = (opcode & 0x00FF) as u8;
-        let nnn = opcode & 0x0FFF;
-        let result = match nibbles {
-            (0x00, _, _, _) => match kk {
-                0xE0 => String::from("CLS"), // 00E0 - CLS: Clear display
-                0xEE => String::from("RET"), // 00EE - RET : Return from subroutine
-                _ => format!("{:x}", opcode),
-            },
-            (0x01, _, _, _) => format!("JP {}", nnn), // 1NNN - JP addr: Jump to location nnn.
-            (0x02, _, _, _) => format!("CALL {}", nnn), // 2NNN - CALL addr: Call subroutine at nnn.
-            (0x03, _, _, _) => format!("SE V{}, {}", x, kk), // 3XKK - SE Vx, byte: Skip next instruction if Vx = kk.
-            (0x04, _, _, _) => format!("SNE V{}, {}", x, kk), // 4XKK - SNE Vx, byte: Skip next instruction if Vx != kk.
-            (0x05, _, _, _) => format!("SE V{}, V{}", x, y), // 5XY0 - SE Vx, Vy: Skip next instruction if Vx = Vy.
-            (0x06, _, _, _) => format!("LD V{}, {}", x, kk), // 6XKK - LD Vx, byte: Set Vx = kk.
-            (0x07, _, _, _) => format!("ADD V{}, {}", x, kk), // 7XKK - ADD Vx, byte: Set Vx = Vx + kk.
-            (0x08, _, _, _) => match n {
-                0x00 => format!("LD V{}, V{}", x, y), //  8XY0 - LD Vx, Vy: Set Vx = Vy.
-                0x01 => format!("OR V{}, V{}", x, y), //  8XY1 - OR Vx, Vy: Set Vx = Vx OR Vy.
-                0x02 => format!("AND V{}, V{}", x, y), //  8XY2 - AND Vx, Vy: Set Vx = Vx AND Vy.
-                0x03 => format!("XOR V{}, V{}", x, y), //  8XY3 - XOR Vx, Vy: Set Vx = Vx XOR Vy.
-                0x04 => format!("ADD V{}, V{}", x, y), //  8XY4 - ADD Vx, Vy: Set Vx = Vx + Vy, set VF = carry.
-                0x05 => format!("SUB V{}, V{}", x, y), //  8XY5 - SUB Vx, Vy: Set Vx = Vx - Vy, set VF = NOT borrow.
-                0x06 => format!("SHR V{}", x),         //  8XY6 - SHR Vx: Set Vx = Vx SHR 1.
-                0x07 => format!("SUBN V{} V{}", x, y), //  8XY7 - SUBN Vx, Vy: Set Vx = Vy - Vx, set VF = NOT borrow.
-                0x0E => format!("SHL V{}", x),         //  8XYE - SHL Vx: Set Vx = Vx SHL 1.
-                _ => format!("{:x}", opcode),
-            },
-            (0x09, _, _, _) => format!("SNE V{}, V{}", x, y), // 9XY0 - SNE Vx, Vy: Skip next instruction if Vx != Vy.
-            (0x0A, _, _, _) => format!("LD I, {}", nnn),      // ANNN - LD I, addr: Set I to NNN
-            (0x0B, _, _, _) => format!("JP V0, {}", nnn), // BNNN - JP V0, addr: Jump to location nnn + V0.
-            (0x0C, _, _, _) => format!("RND V{}, {}", x, kk), // CXKK - RND Vx, byte: Set Vx = random byte AND kk.
-            (0x0D, _, _, _) => format!("DRW V{}, V{}, {}", x, y, n), // DXYN - DRW, Vx, Vy, nibble: Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
-            (0x0E, _, _, _) => match kk {
-                0x9E => format!("SKP V{}", x), //  Ex9E - SKP Vx:  Skip next instruction if key with the value of Vx is pressed.
-                0xA1 => format!("SKNP V{}", x), //  EXA1 - SKNP Vx: Skip next instruction if key with the value of Vx is not pressed.
-                _ => format!("{:x}", opcode),
-            },
-            (0x0F, _, _, _) => match kk {
-                0x07 => format!("LD V{}, DT", x), //  FX07 - LD Vx, DT: Set Vx = delay timer value. The value of DT is placed into Vx.
-                0x0A => format!("LD V{}, K", x), //  FX0A - LD Vx, K: Wait for a key press, store the value of the key in Vx.
-                0x15 => format!("LD DT, V{}", x), //  FX15 - LD DT, Vx: Set delay timer = Vx.
-                0x18 => format!("LD ST, V{}", x), //  FX18 - LD ST, Vx: Set sound timer = Vx.
-                0x1E => format!("ADD I, V{}", x), //  FX1E - ADD I, Vx: Set I = I + Vx.
-                0x29 => format!("LD F, V{}", x), //  FX29 - LD F, Vx: Set I = location of sprite for digit Vx.
-                0x33 => format!("LD B, V{}", x), //  FX33 - LD B, Vx: Store BCD representation of Vx in memory locations I, I+1, and I+2.
-                0x55 => format!("LD I, V{}", x), //  FX55 - LD [I], Vx: Store registers V0 through Vx in memory starting at location I.
-                0x65 => format!("LD V{}, I", x), //  FX65 - Ld Vx, [I]: Read registers V0 through Vx from memory starting at location I.
-                _ => format!("{:x}", opcode),
-            },
-            _ => format!("{:x}", opcode),
+    fn decode_op(&self, opcode: u16) -> Cow<'static, str> {
+        render_opcode(opcode, &self.render_opts())
+    }
+
+    fn render_opts(&self) -> RenderOpts {
+        RenderOpts::default()
+            .radix(self.config.radix)
+            .hex_registers(self.config.hex_registers)
+    }
+}
+
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+// Assembly text of an opcode; operandless mnemonics are borrowed, only instructions with
+// operands allocate
+fn render_opcode(opcode: u16, opts: &RenderOpts) -> Cow<'static, str> {
+    let nibbles = (
+        ((opcode & 0xF000) >> 12) as u8,
+        ((opcode & 0x0F00) >> 8) as u8,
+        ((opcode & 0x00F0) >> 4) as u8,
+        (opcode & 0x000F) as u8,
+    );
+
+    let x = nibbles.1 as usize;
+    let y = nibbles.2 as usize;
+    let n = nibbles.3;
+    let kk = (opcode & 0x00FF) as u8;
+    let nnn = opcode & 0x0FFF;
+    let (v0, vx, vy) = (opts.register(0), opts.register(x), opts.register(y));
+    let (byte, nibble, addr) = (
+        opts.number(kk as u16, 2),
+        opts.number(n as u16, 1),
+        opts.number(nnn, 3),
+    );
+    let result = match nibbles {
+        (0x00, _, _, _) => match opcode {
+            0x00E0 => return Cow::Borrowed("CLS"), // 00E0 - CLS: Clear display
+            0x00EE => return Cow::Borrowed("RET"), // 00EE - RET : Return from subroutine
+            _ => data_directive(opcode),
+        },
+        (0x01, _, _, _) => format!("JP {}", addr), // 1NNN - JP addr: Jump to location nnn.
+        (0x02, _, _, _) => format!("CALL {}", addr), // 2NNN - CALL addr: Call subroutine at nnn.
+        (0x03, _, _, _) => format!("SE {}, {}", vx, byte), // 3XKK - SE Vx, byte: Skip next instruction if Vx = kk.
+        (0x04, _, _, _) => format!("SNE {}, {}", vx, byte), // 4XKK - SNE Vx, byte: Skip next instruction if Vx != kk.
+        (0x05, _, _, 0x00) => format!("SE {}, {}", vx, vy), // 5XY0 - SE Vx, Vy: Skip next instruction if Vx = Vy.
+        (0x05, _, _, 0x02) => format!("LD [I], {}..{}", vx, vy), // 5XY2 - Store Vx through Vy in memory starting at location I (XO-CHIP).
+        (0x05, _, _, 0x03) => format!("LD {}..{}, [I]", vx, vy), // 5XY3 - Read Vx through Vy from memory starting at location I (XO-CHIP).
+        (0x06, _, _, _) => format!("LD {}, {}", vx, byte),       // 6XKK - LD Vx, byte: Set Vx = kk.
+        (0x07, _, _, _) => format!("ADD {}, {}", vx, byte), // 7XKK - ADD Vx, byte: Set Vx = Vx + kk.
+        (0x08, _, _, _) => match n {
+            0x00 => format!("LD {}, {}", vx, vy), //  8XY0 - LD Vx, Vy: Set Vx = Vy.
+            0x01 => format!("OR {}, {}", vx, vy), //  8XY1 - OR Vx, Vy: Set Vx = Vx OR Vy.
+            0x02 => format!("AND {}, {}", vx, vy), //  8XY2 - AND Vx, Vy: Set Vx = Vx AND Vy.
+            0x03 => format!("XOR {}, {}", vx, vy), //  8XY3 - XOR Vx, Vy: Set Vx = Vx XOR Vy.
+            0x04 => format!("ADD {}, {}", vx, vy), //  8XY4 - ADD Vx, Vy: Set Vx = Vx + Vy, set VF = carry.
+            0x05 => format!("SUB {}, {}", vx, vy), //  8XY5 - SUB Vx, Vy: Set Vx = Vx - Vy, set VF = NOT borrow.
+            // Vy is kept when set so the opcode reassembles unchanged
+            0x06 if y == 0 => format!("SHR {}", vx), //  8XY6 - SHR Vx: Set Vx = Vx SHR 1.
+            0x06 => format!("SHR {}, {}", vx, vy),
+            0x07 => format!("SUBN {} {}", vx, vy), //  8XY7 - SUBN Vx, Vy: Set Vx = Vy - Vx, set VF = NOT borrow.
+            0x0E if y == 0 => format!("SHL {}", vx), //  8XYE - SHL Vx: Set Vx = Vx SHL 1.
+            0x0E => format!("SHL {}, {}", vx, vy),
+            _ => data_directive(opcode),
+        },
+        (0x09, _, _, 0x00) => format!("SNE {}, {}", vx, vy), // 9XY0 - SNE Vx, Vy: Skip next instruction if Vx != Vy.
+        (0x0A, _, _, _) => format!("LD I, {}", addr),        // ANNN - LD I, addr: Set I to NNN
+        (0x0B, _, _, _) => format!("JP V0, {}", addr), // BNNN - JP V0, addr: Jump to location nnn + V0.
+        (0x0C, _, _, _) => format!("RND {}, {}", vx, byte), // CXKK - RND Vx, byte: Set Vx = random byte AND kk.
+        (0x0D, _, _, _) => format!("DRW {}, {}, {}", vx, vy, nibble), // DXYN - DRW, Vx, Vy, nibble: Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
+        (0x0E, _, _, _) => match kk {
+            0x9E => format!("SKP {}", vx), //  Ex9E - SKP Vx:  Skip next instruction if key with the value of Vx is pressed.
+            0xA1 => format!("SKNP {}", vx), //  EXA1 - SKNP Vx: Skip next instruction if key with the value of Vx is not pressed.
+            _ => data_directive(opcode),
+        },
+        (0x0F, _, _, _) => match kk {
+            0x07 => format!("LD {}, DT", vx), //  FX07 - LD Vx, DT: Set Vx = delay timer value. The value of DT is placed into Vx.
+            0x0A => format!("LD {}, K", vx), //  FX0A - LD Vx, K: Wait for a key press, store the value of the key in Vx.
+            0x15 => format!("LD DT, {}", vx), //  FX15 - LD DT, Vx: Set delay timer = Vx.
+            0x18 => format!("LD ST, {}", vx), //  FX18 - LD ST, Vx: Set sound timer = Vx.
+            0x1E => format!("ADD I, {}", vx), //  FX1E - ADD I, Vx: Set I = I + Vx.
+            0x29 => format!("LD F, {}", vx), //  FX29 - LD F, Vx: Set I = location of sprite for digit Vx.
+            0x33 => format!("LD B, {}", vx), //  FX33 - LD B, Vx: Store BCD representation of Vx in memory locations I, I+1, and I+2.
+            0x55 => format!("LD I, {} ; stores {}..{}", vx, v0, vx), //  FX55 - LD [I], Vx: Store registers V0 through Vx in memory starting at location I.
+            0x65 => format!("LD {}, I ; loads {}..{}", vx, v0, vx), //  FX65 - Ld Vx, [I]: Read registers V0 through Vx from memory starting at location I.
+            _ => data_directive(opcode),
+        },
+        _ => data_directive(opcode),
+    };
+    Cow::Owned(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Write a ROM to its own file in the temp directory, returning the path
+    fn rom_file(name: &str, rom: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("chiprs_{}.ch8", name));
+        fs::write(&path, rom).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn write_output(disassembler: &Disassembler) -> io::Result<String> {
+        let labels = disassembler.labels();
+        let decoded = disassembler.decode_all(&labels);
+        disassembler.write_file(&decoded, &labels)
+    }
+
+    #[test]
+    fn renders_in_both_styles() {
+        let drw = Decoded::new(0x200, 0xDAB5);
+        assert_eq!(drw.render(&RenderOpts::default()), "DRW V10, V11, 5");
+        let hex = RenderOpts::default().radix(Radix::Hex).hex_registers(true);
+        assert_eq!(drw.render(&hex), "DRW VA, VB, 0x5");
+        assert_eq!(Decoded::new(0x200, 0x60FF).render(&hex), "LD V0, 0xff");
+        assert_eq!(Decoded::new(0x200, 0x1234).render(&hex), "JP 0x234");
+    }
+
+    #[test]
+    fn borrows_operandless_mnemonics() {
+        let opts = RenderOpts::default();
+        assert!(matches!(render_opcode(0x00E0, &opts), Cow::Borrowed("CLS")));
+        assert!(matches!(render_opcode(0x00EE, &opts), Cow::Borrowed("RET")));
+        let jp = render_opcode(0x1234, &opts);
+        assert!(matches!(jp, Cow::Owned(_)));
+        assert_eq!(jp, "JP 564");
+    }
+
+    #[test]
+    fn comments_the_stored_register_range() {
+        let opts = RenderOpts::default();
+        assert_eq!(render_opcode(0xF555, &opts), "LD I, V5 ; stores V0..V5");
+        assert_eq!(render_opcode(0xF565, &opts), "LD V5, I ; loads V0..V5");
+    }
+
+    #[test]
+    fn decodes_hex_text_with_either_prefix() {
+        assert_eq!(
+            decode_hex_text(b"00E0\n0XFF 0x12\n  a2").unwrap(),
+            vec![0x00, 0xE0, 0xFF, 0x12, 0xA2]
+        );
+        assert!(decode_hex_text(b"0x0x12").is_err());
+    }
+
+    #[test]
+    fn names_timer_registers_in_the_register_style() {
+        let hex = RenderOpts::default().hex_registers(true);
+        assert_eq!(
+            timer_comment(0xFA15, &hex).unwrap(),
+            "set delay timer from VA"
+        );
+        assert_eq!(
+            timer_comment(0xFA07, &RenderOpts::default()).unwrap(),
+            "read delay timer into V10"
+        );
+        assert_eq!(timer_comment(0xFA1E, &hex), None);
+    }
+
+    #[test]
+    fn refuses_to_overwrite_the_input() {
+        let rom_path = rom_file("overwrite", &[0x00, 0xE0]);
+        // The same file spelled differently
+        let same_file = std::env::temp_dir().join(".").join("chiprs_overwrite.ch8");
+        let same_file = same_file.to_str().unwrap().to_string();
+        for output_path in &[rom_path.clone(), same_file] {
+            let config = DisassemblerConfig::default().output_path(output_path.clone());
+            let disassembler = Disassembler::new(rom_path.clone(), config);
+            let error = write_output(&disassembler).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+            assert!(error.to_string().contains("use -o"));
+        }
+        assert_eq!(fs::read(&rom_path).unwrap(), vec![0x00, 0xE0]);
+    }
+
+    #[test]
+    fn strict_align_rejects_jumps_to_odd_addresses() {
+        // CLS; JP 0x205; then a word straddled by the jump target
+        let rom_path = rom_file(
+            "strict_align",
+            &[0x00, 0xE0, 0x12, 0x05, 0x00, 0xE0, 0x12, 0x00],
+        );
+        let lenient = Disassembler::new(rom_path.clone(), DisassemblerConfig::default());
+        assert!(lenient.walk_control_flow().is_ok());
+
+        let config = DisassemblerConfig::default().strict_align(true);
+        let strict = Disassembler::new(rom_path, config);
+        let error = strict.walk_control_flow().unwrap_err();
+        assert_eq!((error.source, error.target), (0x202, 0x205));
+        assert_eq!(
+            error.to_string(),
+            "Instruction at [514] leads to odd address [517]"
+        );
+    }
+
+    #[test]
+    fn writes_json_lines() {
+        let rom_path = rom_file("json", &[0x00, 0xE0, 0x61, 0x0A, 0x12, 0x00]);
+        let output_path = std::env::temp_dir().join("chiprs_json.json");
+        let config = DisassemblerConfig::default()
+            .format(OutputFormat::Json)
+            .output_path(output_path.to_str().unwrap().to_string());
+        write_output(&Disassembler::new(rom_path, config)).unwrap();
+
+        let json = fs::read_to_string(&output_path).unwrap();
+        let lines: Vec<DisassembledLine> = serde_json::from_str(&json).unwrap();
+        let line = |address, opcode, mnemonic: &str, operands: &[&str]| DisassembledLine {
+            address,
+            opcode,
+            mnemonic: mnemonic.to_string(),
+            operands: operands.iter().map(|operand| operand.to_string()).collect(),
         };
-        result
+        assert_eq!(
+            lines,
+            vec![
+                line(0x200, 0x00E0, "CLS", &[]),
+                line(0x202, 0x610A, "LD", &["V1", "10"]),
+                line(0x204, 0x1200, "JP", &["512"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn exposes_generated_labels() {
+        // CLS; CALL 0x206; JP 0x200; RET
+        let rom_path = rom_file("symbols", &[0x00, 0xE0, 0x22, 0x06, 0x12, 0x00, 0x00, 0xEE]);
+        let disassembler = Disassembler::new(rom_path, DisassemblerConfig::default());
+        let symbols = disassembler.symbol_table();
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols["L_0200"], 0x200);
+        assert_eq!(symbols["L_0206"], 0x206);
+    }
+
+    #[test]
+    fn lists_with_a_custom_config() {
+        let rom_path = rom_file("config", &[0x00, 0xE0, 0xF2, 0x55]);
+        let config = DisassemblerConfig::default()
+            .radix(Radix::Hex)
+            .verbose(true);
+        let listing = Disassembler::new(rom_path, config).listing();
+        assert_eq!(listing[1], "[0x200]    00e0    CLS ; Clear the display.");
+        assert!(listing[2].starts_with("[0x202]    f255    LD I, V2 ; stores V0..V2"));
+    }
+
+    #[test]
+    fn skips_a_header() {
+        let rom_path = rom_file("header", &[0xCA, 0xFE, 0x00, 0xE0]);
+        let config = DisassemblerConfig::default().skip_bytes(2);
+        let listing = Disassembler::new(rom_path.clone(), config).listing();
+        assert_eq!(listing.len(), 2);
+        assert_eq!(listing[1], "[512]    00e0    CLS");
+
+        // Skipping past the end leaves nothing to decode
+        let config = DisassemblerConfig::default().skip_bytes(9);
+        assert_eq!(Disassembler::new(rom_path, config).listing().len(), 1);
+    }
+
+    #[test]
+    fn indents_instructions_but_not_labels() {
+        let rom_path = rom_file("indent", &[0x00, 0xE0, 0x12, 0x00]);
+        let output_path = std::env::temp_dir().join("chiprs_indent.chasm");
+        let config = DisassemblerConfig::default()
+            .labels(true)
+            .indent(Indent::Spaces(4))
+            .align_operands(true)
+            .output_path(output_path.to_str().unwrap().to_string());
+        write_output(&Disassembler::new(rom_path, config)).unwrap();
+        assert_eq!(
+            fs::read_to_string(&output_path).unwrap(),
+            "L_0200:\n    CLS\n    JP   L_0200\n"
+        );
+    }
+
+    #[test]
+    fn lists_opcodes_a_mode_does_not_support() {
+        // CLS; PLANE 2 (XO-CHIP); HIGH (SCHIP); JP 0x200
+        let rom_path = rom_file(
+            "compatible",
+            &[0x00, 0xE0, 0xF2, 0x01, 0x00, 0xFF, 0x12, 0x00],
+        );
+        let disassembler = Disassembler::new(rom_path, DisassemblerConfig::default());
+        assert!(!disassembler.is_compatible_with(ChipMode::Chip8));
+        assert_eq!(
+            disassembler.incompatible_addresses(ChipMode::Chip8),
+            vec![0x202, 0x204]
+        );
+        assert_eq!(
+            disassembler.incompatible_addresses(ChipMode::SuperChip),
+            vec![0x202]
+        );
+        assert!(disassembler.is_compatible_with(ChipMode::XoChip));
+    }
+
+    #[test]
+    fn writes_a_c_byte_array() {
+        let rom: Vec<u8> = (0..14).collect();
+        let rom_path = rom_file("byte_array", &rom);
+        let output_path = std::env::temp_dir().join("chiprs_byte_array.h");
+        let config = DisassemblerConfig::default()
+            .format(OutputFormat::ByteArray)
+            .array_name(String::from("pong"))
+            .output_path(output_path.to_str().unwrap().to_string());
+        write_output(&Disassembler::new(rom_path, config)).unwrap();
+        assert_eq!(
+            fs::read_to_string(&output_path).unwrap(),
+            "const unsigned char pong[] = {\n    \
+             0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B,\n    \
+             0x0C, 0x0D,\n};\n"
+        );
+    }
+
+    #[test]
+    fn finds_bytes_and_opcodes() {
+        let rom_path = rom_file("find", &[0x00, 0xE0, 0xD1, 0x25, 0x00, 0xE0, 0xE0]);
+        let disassembler = Disassembler::new(rom_path, DisassemblerConfig::default());
+        assert_eq!(disassembler.find(&[0xE0, 0xD1]), vec![0x201]);
+        assert_eq!(disassembler.find(&[0xE0]), vec![0x201, 0x205, 0x206]);
+        assert!(disassembler.find(&[]).is_empty());
+        // Opcodes only match at instruction addresses
+        assert_eq!(disassembler.find_opcode(0x00E0, 0xFFFF), vec![0x200, 0x204]);
+        assert_eq!(disassembler.find_opcode(0xD000, 0xF000), vec![0x202]);
+    }
+
+    #[test]
+    fn disassembles_hex_text_like_binary() {
+        let binary = rom_file("binary_input", &[0x00, 0xE0, 0x12, 0x00, 0xA2, 0x34]);
+        let hex_text = std::env::temp_dir().join("chiprs_hex_input.hex");
+        fs::write(&hex_text, "00E0\n0x1200\n  a234\n").unwrap();
+        let config = DisassemblerConfig::default().input_format(InputFormat::Hex);
+        let listing = Disassembler::new(hex_text.to_str().unwrap().to_string(), config).listing();
+        assert_eq!(listing.len(), 4);
+        assert_eq!(
+            listing,
+            Disassembler::new(binary, DisassemblerConfig::default()).listing()
+        );
+    }
+
+    #[test]
+    fn comments_timer_directions_when_verbose() {
+        let rom_path = rom_file("timers", &[0xF0, 0x07, 0xF0, 0x15, 0xF3, 0x18]);
+        let config = DisassemblerConfig::default().verbose(true);
+        let listing = Disassembler::new(rom_path, config).listing();
+        assert!(listing[1].ends_with("LD V0, DT ; read delay timer into V0"));
+        assert!(listing[2].ends_with("LD DT, V0 ; set delay timer from V0"));
+        assert!(listing[3].ends_with("LD ST, V3 ; set sound timer from V3, beeping while non-zero"));
+    }
+
+    #[test]
+    fn finds_data_targets_inside_code() {
+        // LD I, 0x203 (inside the DRW below); DRW V0, V1, 1; LD I, 0x208; loop: JP loop; data
+        let rom = [0xA2, 0x03, 0xD0, 0x11, 0xA2, 0x08, 0x12, 0x06, 0xFF];
+        let disassembler =
+            Disassembler::new(rom_file("overlaps", &rom), DisassemblerConfig::default());
+        assert_eq!(disassembler.code_data_overlaps(), vec![0x203]);
+    }
+
+    #[test]
+    fn truncates_after_max_instructions() {
+        let rom = [0x00, 0xE0].repeat(5);
+        let listing = |name: &str, rom: &[u8], max| {
+            let config = DisassemblerConfig::default().max_instructions(max);
+            Disassembler::new(rom_file(name, rom), config).listing()
+        };
+        let truncated = listing("truncated", &rom, 3);
+        assert_eq!(truncated.len(), 5);
+        assert_eq!(truncated[4], "; ... truncated");
+        assert_eq!(listing("not_truncated", &rom, 5).len(), 6);
+
+        // A trailing odd byte is decoded as one more item
+        let mut odd = rom.clone();
+        odd.push(7);
+        assert_eq!(listing("odd", &odd, 5).last().unwrap(), TRUNCATED_MARKER);
+        assert_eq!(listing("odd", &odd, 6).len(), 7);
+    }
+
+    #[test]
+    fn names_addresses_from_a_symbol_file() {
+        // CLS; CLS; main: LD V0, 1; JP main
+        let rom = [0x00, 0xE0, 0x00, 0xE0, 0x60, 0x01, 0x12, 0x04];
+        let mut disassembler =
+            Disassembler::new(rom_file("symbol_file", &rom), DisassemblerConfig::default());
+        let symbol_path = std::env::temp_dir().join("chiprs_symbol_file.sym");
+        fs::write(&symbol_path, "; symbols\nmain 0x204\nstart = 512 ; entry\n").unwrap();
+        disassembler
+            .load_symbols(symbol_path.to_str().unwrap())
+            .unwrap();
+
+        let listing = disassembler.listing();
+        assert!(listing.iter().any(|line| line.ends_with("JP main")));
+        assert!(listing.contains(&String::from("main:")));
+        assert!(listing.contains(&String::from("start:")));
+        assert_eq!(disassembler.symbol_table()["main"], 0x204);
+
+        fs::write(&symbol_path, "main\n").unwrap();
+        assert!(disassembler
+            .load_symbols(symbol_path.to_str().unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn reports_the_registers_a_rom_uses() {
+        // LD V0, 1; loop: ADD V0, V1; SE V0, 5; JP loop; end: JP end; then an unreachable LD VF, 1
+        let rom = [
+            0x60, 0x01, 0x80, 0x14, 0x30, 0x05, 0x12, 0x02, 0x12, 0x08, 0x6F, 0x01,
+        ];
+        let usage = Disassembler::new(rom_file("registers", &rom), DisassemblerConfig::default())
+            .register_usage();
+        assert!(usage[0].read && usage[0].written);
+        assert!(usage[1].read && !usage[1].written);
+        // ADD Vx, Vy sets the carry
+        assert!(!usage[15].read && usage[15].written);
+        assert!(usage[2..15].iter().all(RegUsage::is_unused));
+    }
+
+    #[test]
+    fn flags_jumps_into_the_middle_of_instructions() {
+        // LD V0, 1; CALL 0x205; JP 0x203; RET
+        let rom = [0x60, 0x01, 0x22, 0x05, 0x12, 0x03, 0x00, 0xEE];
+        let disassembler =
+            Disassembler::new(rom_file("misaligned", &rom), DisassemblerConfig::default());
+        assert_eq!(
+            disassembler.misaligned_targets(),
+            vec![(0x202, 0x205), (0x204, 0x203)]
+        );
+
+        // LD V0, 1; CALL 0x206; JP 0x200; RET
+        let rom = [0x60, 0x01, 0x22, 0x06, 0x12, 0x00, 0x00, 0xEE];
+        let disassembler =
+            Disassembler::new(rom_file("aligned", &rom), DisassemblerConfig::default());
+        assert!(disassembler.misaligned_targets().is_empty());
+    }
+
+    #[test]
+    fn splits_basic_blocks_at_skips_and_jumps() {
+        // LD V0, 1; SE V0, 1; JP 0x20A; CLS; end: JP end; ADD V0, 1; JP 0x202
+        let rom = [
+            0x60, 0x01, 0x30, 0x01, 0x12, 0x0A, 0x00, 0xE0, 0x12, 0x08, 0x70, 0x01, 0x12, 0x02,
+        ];
+        let disassembler =
+            Disassembler::new(rom_file("blocks", &rom), DisassemblerConfig::default());
+        let blocks: Vec<(u16, u16, u16)> = disassembler
+            .basic_blocks()
+            .iter()
+            .map(|block| (block.start, block.end, block.terminator.opcode))
+            .collect();
+        assert_eq!(
+            blocks,
+            vec![
+                (0x200, 0x202, 0x6001),
+                (0x202, 0x204, 0x3001),
+                (0x204, 0x206, 0x120A),
+                (0x206, 0x208, 0x00E0),
+                (0x208, 0x20A, 0x1208),
+                (0x20A, 0x20E, 0x1202),
+            ]
+        );
+    }
+
+    #[test]
+    fn follows_a_leading_jump_to_the_entry_point() {
+        let entry_points = |name: &str, rom: &[u8]| {
+            Disassembler::new(rom_file(name, rom), DisassemblerConfig::default()).entry_points()
+        };
+        // JP main; a 2-byte header; main: CLS
+        assert_eq!(
+            entry_points("entry_jump", &[0x12, 0x04, 0xAB, 0xCD, 0x00, 0xE0]),
+            vec![0x200, 0x204]
+        );
+        assert_eq!(entry_points("entry_loop", &[0x12, 0x00]), vec![0x200]);
+        assert_eq!(entry_points("entry_plain", &[0x00, 0xE0]), vec![0x200]);
+        assert_eq!(entry_points("entry_empty", &[]), vec![0x200]);
+    }
+
+    #[test]
+    fn summarizes_trailing_zero_padding() {
+        let listing = |name: &str, rom: &[u8], config| {
+            Disassembler::new(rom_file(name, rom), config).listing()
+        };
+
+        // CLS; loop: JP loop; then 8 zero bytes
+        let rom = [0x00, 0xE0, 0x12, 0x02, 0, 0, 0, 0, 0, 0, 0, 0];
+        let padded = listing("padding", &rom, DisassemblerConfig::default());
+        assert_eq!(padded.len(), 4);
+        assert!(padded[3].ends_with("DB 0, 0, 0, 0, 0, 0, 0, 0 ; 8 bytes of padding"));
+
+        // An odd number of zeros after the last instruction
+        let padded = listing(
+            "odd_padding",
+            &[0x60, 0x00, 0, 0, 0, 0, 0],
+            DisassemblerConfig::default(),
+        );
+        assert!(padded.last().unwrap().ends_with("; 5 bytes of padding"));
+
+        // Too few zeros to bother
+        assert_eq!(
+            listing(
+                "short_padding",
+                &[0x00, 0xE0, 0, 0],
+                DisassemblerConfig::default()
+            )
+            .len(),
+            3
+        );
+
+        // CALL 0x206; loop: JP loop; a labeled buffer of zeros stays out of the padding
+        let rom = [0x22, 0x06, 0x12, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let padded = listing(
+            "labeled_padding",
+            &rom,
+            DisassemblerConfig::default().labels(true),
+        );
+        assert!(padded.contains(&String::from("L_0206:")));
+        assert!(padded.last().unwrap().ends_with("; 6 bytes of padding"));
+
+        // The summary still assembles back to the zeros
+        let output_path = std::env::temp_dir().join("chiprs_labeled_padding.chasm");
+        let config = DisassemblerConfig::default()
+            .labels(true)
+            .output_path(output_path.to_str().unwrap().to_string());
+        write_output(&Disassembler::new(
+            rom_file("labeled_padding", &rom),
+            config,
+        ))
+        .unwrap();
+        let source = fs::read_to_string(&output_path).unwrap();
+        assert_eq!(crate::assembler::assemble(&source).unwrap(), rom.to_vec());
     }
 }
@@ -4,32 +4,86 @@
 // which are always 8 X N (N is pixel height).
 // Font set sprites: characters 0-9 and A-F
 // to be printed directly within 8x5 grid.
+// XO-CHIP adds up to 4 bitplanes, selected with PLANE n:
+// each pixel then holds a palette index, one bit per plane.
 
+use alloc::string::String;
+
+// SCHIP hi-res mode (00FF) doubles both dimensions
 const WIDTH: usize = 64;
 const HEIGHT: usize = 32;
+const HIRES_WIDTH: usize = 128;
+const HIRES_HEIGHT: usize = 64;
+const PLANES: usize = 4;
 
 pub struct Display {
-    frame_buffer: [u8; WIDTH * HEIGHT],
+    frame_buffer: [u8; HIRES_WIDTH * HIRES_HEIGHT],
+    plane_mask: u8,
+    hires: bool,
 }
 
 impl Display {
     pub fn new() -> Display {
         Display {
-            frame_buffer: [0; WIDTH * HEIGHT],
+            frame_buffer: [0; HIRES_WIDTH * HIRES_HEIGHT],
+            plane_mask: 0x01,
+            hires: false,
+        }
+    }
+
+    // Switch between 64x32 and 128x64, which also clears the screen
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.frame_buffer = [0; HIRES_WIDTH * HIRES_HEIGHT];
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_WIDTH
+        } else {
+            WIDTH
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_HEIGHT
+        } else {
+            HEIGHT
         }
     }
 
+    // Select the bitplanes affected by clear/draw, one bit per plane
+    pub fn set_plane(&mut self, mask: u8) {
+        self.plane_mask = mask & ((1 << PLANES) - 1);
+    }
+
+    pub fn plane_mask(&self) -> u8 {
+        self.plane_mask
+    }
+
+    // Number of selected planes, each of which consumes its own sprite rows when drawing
+    pub fn plane_count(&self) -> usize {
+        self.plane_mask.count_ones() as usize
+    }
+
+    // Clear the selected planes only
     pub fn clear(&mut self) {
         for pixel in self.frame_buffer.iter_mut() {
-            *pixel = 0;
+            *pixel &= !self.plane_mask;
         }
     }
 
     pub fn get_index(&self, x: usize, y: usize) -> usize {
-        x + WIDTH * y
+        x + self.width() * y
     }
 
     // Set/unset pixels in display_buffer, return true/false if collision detected
+    // With several planes selected, the sprite holds the rows of each plane in turn
     pub fn draw(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
         // Sprite height can be between 1 and 15 bytes, sprite width is 8 bits
         // Bit pattern shows which pixel to set/unset using XOR
@@ -39,17 +93,29 @@ impl Display {
         // 00000000
         // 0
         let mut collision = false;
+        let plane_count = self.plane_count();
+        if plane_count == 0 {
+            return collision;
+        }
+        let height = sprite.len() / plane_count;
+        let plane_mask = self.plane_mask;
+        let (screen_width, screen_height) = (self.width(), self.height());
+        let planes = (0..PLANES).filter(|plane| plane_mask & (1 << plane) != 0);
 
-        for (i, row) in sprite.iter().enumerate() {
-            let y_coord = (y + i) % 32;
-            for col in 0..8 {
-                let val = *row >> (7 - col) & 0x01;
-                let x_coord = (x + col) % 64;
-                let offset = self.get_index(x_coord, y_coord);
-                let prev_val = self.frame_buffer[offset];
-                self.frame_buffer[offset] ^= val;
-                if prev_val == 1 && self.frame_buffer[offset] == 0 {
-                    collision = true;
+        for (rows, plane) in sprite.chunks(height.max(1)).zip(planes) {
+            let bit = 1u8 << plane;
+            for (i, row) in rows.iter().enumerate() {
+                let y_coord = (y + i) % screen_height;
+                for col in 0..8 {
+                    if *row >> (7 - col) & 0x01 == 0 {
+                        continue;
+                    }
+                    let x_coord = (x + col) % screen_width;
+                    let offset = self.get_index(x_coord, y_coord);
+                    if self.frame_buffer[offset] & bit != 0 {
+                        collision = true;
+                    }
+                    self.frame_buffer[offset] ^= bit;
                 }
             }
         }
@@ -57,7 +123,25 @@ impl Display {
         collision
     }
 
+    // Pixels of the active resolution, row by row, as palette indices
+    // combining the planes (bit n set = lit on plane n)
     pub fn get_frame_buffer(&self) -> &[u8] {
-        &self.frame_buffer
+        &self.frame_buffer[..self.width() * self.height()]
+    }
+
+    // Text rendering of the active resolution, '#' for lit pixels and '.' for unlit ones
+    pub fn to_ascii(&self) -> String {
+        let mut ascii = String::with_capacity((self.width() + 1) * self.height());
+        for row in self.get_frame_buffer().chunks(self.width()) {
+            ascii.extend(row.iter().map(|&pixel| if pixel != 0 { '#' } else { '.' }));
+            ascii.push('\n');
+        }
+        ascii
+    }
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
     }
 }
@@ -0,0 +1,16 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod assembler;
+pub mod bus;
+#[cfg(feature = "std")]
+pub mod chip8;
+pub mod cpu;
+#[cfg(feature = "std")]
+pub mod disassembler;
+pub mod display;
+pub mod font;
+pub mod opcodes;
+pub mod ram;
@@ -1,14 +1,11 @@
-#[allow(dead_code)]
-mod assembler;
-mod bus;
-mod chip8;
-mod cpu;
-mod disassembler;
-mod display;
-mod font;
-mod ram;
-use crate::{assembler::Assembler, chip8::Chip8};
-use crate::disassembler::Disassembler;
+use chiprs::disassembler::{
+    Disassembler, DisassemblerConfig, Indent, InputFormat, OutputFormat, Radix,
+};
+use chiprs::opcodes::{ChipMode, OPCODES};
+use chiprs::{
+    assembler::{Assembler, AssemblerConfig, RegisterStyle, RomFormat},
+    chip8::Chip8,
+};
 
 #[macro_use]
 extern crate clap;
@@ -17,17 +14,114 @@ use clap::App;
 fn main() {
     let yaml = load_yaml!("cli.yml");
     let matches = App::from_yaml(yaml).get_matches();
+
+    if matches.is_present("list-opcodes") {
+        for spec in OPCODES {
+            println!(
+                "{}  {:<20}{}",
+                spec.pattern, spec.mnemonic, spec.description
+            );
+        }
+        return;
+    }
+
     let source_file = matches
         .value_of("INPUT")
         .expect("Unable to read file.")
         .to_string();
 
     if matches.is_present("disassemble") {
-        let disassembler = Disassembler::new(source_file);
+        let mut config = DisassemblerConfig::default()
+            .strict_align(matches.is_present("strict-align"))
+            .labels(matches.is_present("labels"))
+            .verbose(matches.is_present("verbose"))
+            .quiet(matches.is_present("quiet"))
+            .align_operands(matches.is_present("align"));
+        if let Some(output_path) = matches.value_of("output") {
+            config = config.output_path(output_path.to_string());
+        }
+        if matches.is_present("json") {
+            config = config.format(OutputFormat::Json);
+        }
+        if let Some(array_name) = matches.value_of("c-array") {
+            config = config
+                .format(OutputFormat::ByteArray)
+                .array_name(array_name.to_string());
+        }
+        if matches.is_present("hex-registers") {
+            config = config.hex_registers(true);
+        }
+        if let Some(max) = matches.value_of("max-instructions") {
+            config = config
+                .max_instructions(max.parse().expect("Invalid maximum number of instructions"));
+        }
+        if let Some(skip) = matches.value_of("skip") {
+            config = config.skip_bytes(skip.parse().expect("Invalid number of bytes to skip"));
+        }
+        if let Some(indent) = matches.value_of("indent") {
+            config = config.indent(match indent {
+                "tab" => Indent::Tab,
+                spaces => Indent::Spaces(spaces.parse().expect("Invalid indentation")),
+            });
+        }
+        if matches.value_of("input-format") == Some("hex") {
+            config = config.input_format(InputFormat::Hex);
+        }
+        if matches.is_present("hex") {
+            config = config.radix(Radix::Hex);
+        }
+        let mut disassembler = Disassembler::new(source_file, config);
+        if let Some(symbols) = matches.value_of("symbols") {
+            if let Err(e) = disassembler.load_symbols(symbols) {
+                println!("Error loading symbols from {}: {}", symbols, e);
+                return;
+            }
+        }
         disassembler.run();
     } else if matches.is_present("assemble") {
-        let assembler = Assembler::new(source_file);
+        let mode = match matches.value_of("mode") {
+            Some("schip") => ChipMode::SuperChip,
+            Some("xochip") => ChipMode::XoChip,
+            _ => ChipMode::Chip8,
+        };
+        let mut config = AssemblerConfig::default()
+            .mode(mode)
+            .lint(matches.is_present("lint"));
+        match matches.value_of("output-format") {
+            Some("hex") => config = config.output_format(RomFormat::Hex),
+            Some("ihex") => config = config.output_format(RomFormat::IntelHex),
+            _ => {}
+        }
+        match matches.value_of("registers") {
+            Some("hex") => config = config.register_style(RegisterStyle::Hex),
+            Some("dec") => config = config.register_style(RegisterStyle::Dec),
+            _ => {}
+        }
+        if let Some(base) = matches.value_of("base") {
+            let address = match base.strip_prefix("0x") {
+                Some(hex) => usize::from_str_radix(hex, 16),
+                None => base.parse(),
+            };
+            config = config.start_address(address.expect("Invalid base address"));
+        }
+        let mut assembler = match Assembler::with_config(source_file.clone(), config) {
+            Ok(assembler) => assembler,
+            Err(e) => {
+                println!("Error reading {}: {}", source_file, e);
+                return;
+            }
+        };
+        if let Some(output_path) = matches.value_of("output") {
+            assembler.set_output_path(output_path.to_string());
+        }
         assembler.run();
+        if let Some(symbols) = matches.value_of("export-symbols") {
+            if assembler.errors().is_empty() {
+                if let Err(e) = assembler.write_symbol_map(symbols) {
+                    println!("Error writing symbols to {}: {}", symbols, e);
+                }
+            }
+        }
     } else {
         let mut chip8 = Chip8::new(source_file);
         chip8.run();
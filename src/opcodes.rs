@@ -0,0 +1,129 @@
+// Reference table of the classic CHIP-8 instruction set.
+// Patterns use hex digits for fixed nibbles, and the usual placeholders for operands:
+// NNN (12-bit address), KK (byte), N (nibble), X/Y (registers).
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OpcodeSpec {
+    pub mnemonic: &'static str,
+    pub pattern: &'static str,
+    pub description: &'static str,
+}
+
+impl OpcodeSpec {
+    // Bits of the opcode fixed by the pattern
+    pub fn mask(&self) -> u16 {
+        self.pattern.chars().fold(0, |mask, c| {
+            mask << 4 | if c.is_ascii_hexdigit() { 0xF } else { 0x0 }
+        })
+    }
+
+    // Value of the fixed bits of the pattern
+    pub fn value(&self) -> u16 {
+        self.pattern.chars().fold(0, |value, c| {
+            value << 4 | c.to_digit(16).unwrap_or(0) as u16
+        })
+    }
+
+    pub fn matches(&self, opcode: u16) -> bool {
+        opcode & self.mask() == self.value()
+    }
+}
+
+// Find the instruction matching an opcode, more specific patterns (00E0, 00EE) come first
+pub fn lookup(opcode: u16) -> Option<&'static OpcodeSpec> {
+    OPCODES.iter().find(|spec| spec.matches(opcode))
+}
+
+// Interpreter variants, each one supporting the opcodes of the previous ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChipMode {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+// Least capable interpreter an opcode is legal on, None if no interpreter knows it
+pub fn required_mode(opcode: u16) -> Option<ChipMode> {
+    let kk = opcode & 0x00FF;
+    match opcode & 0xF000 {
+        // 00Cn scroll down, 00FB-00FF scroll right/left, exit, low/high resolution
+        0x0000 if opcode & 0xFFF0 == 0x00C0 || (0x00FB..=0x00FF).contains(&opcode) => {
+            Some(ChipMode::SuperChip)
+        }
+        // 00Dn scroll up
+        0x0000 if opcode & 0xFFF0 == 0x00D0 => Some(ChipMode::XoChip),
+        // Dxy0 16x16 sprite
+        0xD000 if opcode & 0x000F == 0 => Some(ChipMode::SuperChip),
+        // 5xy2/5xy3 save/load a range of registers
+        0x5000 if matches!(opcode & 0x000F, 0x2 | 0x3) => Some(ChipMode::XoChip),
+        // Fx30 big font, Fx75/Fx85 flag registers
+        0xF000 if matches!(kk, 0x30 | 0x75 | 0x85) => Some(ChipMode::SuperChip),
+        // F000 long I load, Fn01 plane, F002 audio pattern, Fx3A pitch
+        0xF000 if opcode == 0xF000 || opcode == 0xF002 || matches!(kk, 0x01 | 0x3A) => {
+            Some(ChipMode::XoChip)
+        }
+        _ => lookup(opcode).map(|_| ChipMode::Chip8),
+    }
+}
+
+#[rustfmt::skip]
+pub const OPCODES: &[OpcodeSpec] = &[
+    OpcodeSpec { mnemonic: "CLS", pattern: "00E0", description: "Clear the display." },
+    OpcodeSpec { mnemonic: "RET", pattern: "00EE", description: "Return from a subroutine." },
+    OpcodeSpec { mnemonic: "SYS addr", pattern: "0NNN", description: "Jump to a machine code routine at nnn (ignored by modern interpreters)." },
+    OpcodeSpec { mnemonic: "JP addr", pattern: "1NNN", description: "Jump to location nnn." },
+    OpcodeSpec { mnemonic: "CALL addr", pattern: "2NNN", description: "Call subroutine at nnn." },
+    OpcodeSpec { mnemonic: "SE Vx, byte", pattern: "3XKK", description: "Skip next instruction if Vx = kk." },
+    OpcodeSpec { mnemonic: "SNE Vx, byte", pattern: "4XKK", description: "Skip next instruction if Vx != kk." },
+    OpcodeSpec { mnemonic: "SE Vx, Vy", pattern: "5XY0", description: "Skip next instruction if Vx = Vy." },
+    OpcodeSpec { mnemonic: "LD Vx, byte", pattern: "6XKK", description: "Set Vx = kk." },
+    OpcodeSpec { mnemonic: "ADD Vx, byte", pattern: "7XKK", description: "Set Vx = Vx + kk." },
+    OpcodeSpec { mnemonic: "LD Vx, Vy", pattern: "8XY0", description: "Set Vx = Vy." },
+    OpcodeSpec { mnemonic: "OR Vx, Vy", pattern: "8XY1", description: "Set Vx = Vx OR Vy." },
+    OpcodeSpec { mnemonic: "AND Vx, Vy", pattern: "8XY2", description: "Set Vx = Vx AND Vy." },
+    OpcodeSpec { mnemonic: "XOR Vx, Vy", pattern: "8XY3", description: "Set Vx = Vx XOR Vy." },
+    OpcodeSpec { mnemonic: "ADD Vx, Vy", pattern: "8XY4", description: "Set Vx = Vx + Vy, set VF = carry." },
+    OpcodeSpec { mnemonic: "SUB Vx, Vy", pattern: "8XY5", description: "Set Vx = Vx - Vy, set VF = NOT borrow." },
+    OpcodeSpec { mnemonic: "SHR Vx", pattern: "8XY6", description: "Set Vx = Vx SHR 1." },
+    OpcodeSpec { mnemonic: "SUBN Vx, Vy", pattern: "8XY7", description: "Set Vx = Vy - Vx, set VF = NOT borrow." },
+    OpcodeSpec { mnemonic: "SHL Vx", pattern: "8XYE", description: "Set Vx = Vx SHL 1." },
+    OpcodeSpec { mnemonic: "SNE Vx, Vy", pattern: "9XY0", description: "Skip next instruction if Vx != Vy." },
+    OpcodeSpec { mnemonic: "LD I, addr", pattern: "ANNN", description: "Set I = nnn." },
+    OpcodeSpec { mnemonic: "JP V0, addr", pattern: "BNNN", description: "Jump to location nnn + V0." },
+    OpcodeSpec { mnemonic: "RND Vx, byte", pattern: "CXKK", description: "Set Vx = random byte AND kk." },
+    OpcodeSpec { mnemonic: "DRW Vx, Vy, nibble", pattern: "DXYN", description: "Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision." },
+    OpcodeSpec { mnemonic: "SKP Vx", pattern: "EX9E", description: "Skip next instruction if key with the value of Vx is pressed." },
+    OpcodeSpec { mnemonic: "SKNP Vx", pattern: "EXA1", description: "Skip next instruction if key with the value of Vx is not pressed." },
+    OpcodeSpec { mnemonic: "LD Vx, DT", pattern: "FX07", description: "Set Vx = delay timer value." },
+    OpcodeSpec { mnemonic: "LD Vx, K", pattern: "FX0A", description: "Wait for a key press, store the value of the key in Vx." },
+    OpcodeSpec { mnemonic: "LD DT, Vx", pattern: "FX15", description: "Set delay timer = Vx." },
+    OpcodeSpec { mnemonic: "LD ST, Vx", pattern: "FX18", description: "Set sound timer = Vx." },
+    OpcodeSpec { mnemonic: "ADD I, Vx", pattern: "FX1E", description: "Set I = I + Vx." },
+    OpcodeSpec { mnemonic: "LD F, Vx", pattern: "FX29", description: "Set I = location of sprite for digit Vx." },
+    OpcodeSpec { mnemonic: "LD B, Vx", pattern: "FX33", description: "Store BCD representation of Vx in memory locations I, I+1, and I+2." },
+    OpcodeSpec { mnemonic: "LD [I], Vx", pattern: "FX55", description: "Store registers V0 through Vx in memory starting at location I." },
+    OpcodeSpec { mnemonic: "LD Vx, [I]", pattern: "FX65", description: "Read registers V0 through Vx from memory starting at location I." },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_the_35_classic_opcodes_once() {
+        let mut patterns: Vec<_> = OPCODES.iter().map(|spec| spec.pattern).collect();
+        patterns.sort_unstable();
+        patterns.dedup();
+        assert_eq!(OPCODES.len(), 35);
+        assert_eq!(patterns.len(), 35);
+    }
+
+    #[test]
+    fn looks_up_the_most_specific_pattern() {
+        assert_eq!(lookup(0x00E0).unwrap().mnemonic, "CLS");
+        assert_eq!(lookup(0x0123).unwrap().mnemonic, "SYS addr");
+        assert_eq!(lookup(0xD123).unwrap().pattern, "DXYN");
+        assert_eq!(lookup(0x8AB6).unwrap().pattern, "8XY6");
+        assert!(lookup(0x8AB8).is_none());
+    }
+}
@@ -1,6 +1,8 @@
+use core::ops::Range;
+
 use crate::font;
 
-const RAM_SIZE: usize = 4096;
+pub const RAM_SIZE: usize = 4096;
 const START_ROM: usize = 512; // 0x200
 
 pub struct Ram {
@@ -33,4 +35,26 @@ impl Ram {
     pub fn read_bytes(&self, start_idx: usize, stop_idx: usize) -> &[u8] {
         &self.memory[start_idx..stop_idx]
     }
+
+    // Bounds-checked view of a memory region, None if it isn't entirely within memory
+    pub fn slice(&self, range: Range<u16>) -> Option<&[u8]> {
+        self.memory.get(range.start as usize..range.end as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slices_within_memory_only() {
+        let ram = Ram::new(&[1, 2, 3]);
+        assert_eq!(ram.slice(0x200..0x203), Some(&[1, 2, 3][..]));
+        assert_eq!(ram.slice(0xFFE..0x1001), None);
+        assert_eq!(ram.slice(0x1000..0x1002), None);
+        // Empty ranges are fine anywhere up to the end of memory
+        assert_eq!(ram.slice(0x300..0x300), Some(&[][..]));
+        assert_eq!(ram.slice(0x1000..0x1000), Some(&[][..]));
+        assert_eq!(ram.slice(0xFFE..0x1000).map(<[u8]>::len), Some(2));
+    }
 }
@@ -0,0 +1,84 @@
+#![cfg(feature = "std")]
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn chiprs(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_chiprs"))
+        .args(args)
+        .output()
+        .expect("Unable to run chiprs")
+}
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn lists_opcodes() {
+    let output = chiprs(&["--list-opcodes"]);
+    assert!(output.status.success());
+    let listing = String::from_utf8(output.stdout).unwrap();
+    assert!(listing.lines().any(|line| line.starts_with("00E0  CLS")));
+}
+
+#[test]
+fn assembles_a_file() {
+    let dir = scratch_dir("cli_assemble");
+    let source = dir.join("loop.chasm");
+    fs::write(&source, "loop:\nCLS\nJP loop\n").unwrap();
+    let output = chiprs(&["-a", source.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read(dir.join("loop_a.ch8")).unwrap(),
+        vec![0x00, 0xE0, 0x12, 0x00]
+    );
+}
+
+// Malformed source is reported, never a panic
+#[test]
+fn survives_malformed_source() {
+    let dir = scratch_dir("cli_malformed");
+    let sources = [
+        "LD",
+        "LD V0,",
+        "DRW V0 V1",
+        "JP loop - 600",
+        "DB <<>>4096, '",
+        "DA \"unterminated",
+        "x EQU x + 1",
+        ".org",
+        "SE V99, é",
+        "label: label: ,,, ;",
+    ];
+    for (idx, text) in sources.iter().enumerate() {
+        let source = dir.join(format!("{}.chasm", idx));
+        fs::write(&source, text).unwrap();
+        let output = chiprs(&["-a", source.to_str().unwrap()]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{:?}: {}", text, stderr);
+        assert!(!stderr.contains("panicked"), "{:?}: {}", text, stderr);
+    }
+}
+
+#[test]
+fn writes_nothing_for_an_empty_source() {
+    let dir = scratch_dir("cli_empty");
+    let source = dir.join("empty.chasm");
+    fs::write(&source, "  \n\n").unwrap();
+    let _ = fs::remove_file(dir.join("empty_a.ch8"));
+    let output = chiprs(&["-a", source.to_str().unwrap()]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("is empty, no instructions assembled"));
+    assert!(!dir.join("empty_a.ch8").exists());
+
+    // Comments alone assemble to nothing either
+    fs::write(&source, "; nothing yet\n").unwrap();
+    let output = chiprs(&["-a", source.to_str().unwrap()]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("no instructions assembled, no file written"));
+    assert!(!dir.join("empty_a.ch8").exists());
+}
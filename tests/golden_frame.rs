@@ -0,0 +1,12 @@
+#![cfg(feature = "std")]
+
+use chiprs::assembler::assemble;
+use chiprs::cpu::render_ascii;
+
+// The whole stack, from assembly source to the frame the interpreter draws
+#[test]
+fn renders_ibm_logo() {
+    let rom = assemble(include_str!("golden/ibm.chasm")).unwrap();
+    let frame = render_ascii(&rom, 20);
+    assert_eq!(frame, include_str!("golden/ibm.txt"));
+}
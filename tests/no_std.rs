@@ -0,0 +1,17 @@
+use std::process::Command;
+
+// The interpreter core has to keep building without std, for embedded targets
+#[test]
+fn core_builds_without_std() {
+    let output = Command::new(env!("CARGO"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["check", "--lib", "--no-default-features", "--target-dir"])
+        .arg(concat!(env!("CARGO_TARGET_TMPDIR"), "/no_std"))
+        .output()
+        .expect("Unable to run cargo");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -0,0 +1,67 @@
+#![cfg(feature = "std")]
+
+use chiprs::assembler::Assembler;
+use chiprs::disassembler::{Decoded, Disassembler, DisassemblerConfig, Indent, Radix, RenderOpts};
+use chiprs::opcodes::ChipMode;
+use std::fs;
+use std::path::PathBuf;
+
+// Every opcode must disassemble to text that assembles back to the same bytes
+fn assert_round_trips(opts: &RenderOpts) {
+    let mut failures = Vec::new();
+    for opcode in 0..=0xFFFFu16 {
+        let text = Decoded::new(0x200, opcode).render(opts);
+        let bytes = Assembler::builder()
+            .source(text.clone())
+            .mode(ChipMode::XoChip)
+            .assemble();
+        match bytes {
+            Ok(ref b) if *b == opcode.to_be_bytes() => {}
+            _ => failures.push(format!("{:04X} -> {}", opcode, text)),
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} opcodes failed: {:?}",
+        failures.len(),
+        &failures[..failures.len().min(20)]
+    );
+}
+
+#[test]
+fn every_opcode_round_trips() {
+    assert_round_trips(&RenderOpts::default());
+}
+
+#[test]
+fn every_opcode_round_trips_in_hex() {
+    assert_round_trips(&RenderOpts::default().radix(Radix::Hex).hex_registers(true));
+}
+
+// A disassembled ROM, generated labels and all, must reassemble to the same bytes
+#[test]
+fn labeled_listing_reassembles() {
+    let source = "CLS\nloop:\nLD V0, 5\nCALL sub\nSE V0, 3\nJP loop\nJP V0, loop\nLD I, sprite\n\
+                  DRW V0, V1, 1\nsub:\nADD V0, 1\nRET\nsprite:\nDB 0xF0, 0x90, 0x60\n";
+    let rom = Assembler::from_source(source.to_string())
+        .assemble()
+        .unwrap();
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let rom_path = dir.join("round_trip.ch8");
+    let listing_path = dir.join("round_trip.chasm");
+    fs::write(&rom_path, &rom).unwrap();
+
+    let config = DisassemblerConfig::default()
+        .labels(true)
+        .verbose(true)
+        .align_operands(true)
+        .indent(Indent::Spaces(4))
+        .quiet(true)
+        .output_path(listing_path.to_str().unwrap().to_string());
+    Disassembler::new(rom_path.to_str().unwrap().to_string(), config).run();
+    let listing = fs::read_to_string(&listing_path).unwrap();
+    assert!(listing.contains("L_0202:\n"));
+    assert!(listing.contains("JP   L_0202"));
+
+    assert_eq!(Assembler::from_source(listing).assemble().unwrap(), rom);
+}